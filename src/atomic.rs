@@ -0,0 +1,105 @@
+use super::sidecar_path;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Replaces the content of a file so that it holds either the old or the new data,
+/// even if the process is killed or the disk fills up in the middle of writing.
+///
+/// The data is written to a temporary file in the same directory, synced to disk
+/// and renamed over the original, then the directory itself is synced.
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&dir)?;
+    let temp_path = temp_path(path);
+    let result = write_temp_file(path, &temp_path, data)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .and_then(|_| sync_dir(&dir));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    sidecar_path(path, &format!("{}.tmp", std::process::id()))
+}
+
+fn write_temp_file(path: &Path, temp_path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+    // Keep the permissions of the file being replaced
+    match fs::metadata(path) {
+        Ok(metadata) => file.set_permissions(metadata.permissions())?,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened as files on other platforms
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn replaces_the_file_without_leaving_a_temporary_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vault").join("totp.safe");
+        write_file(&path, b"first").unwrap();
+        write_file(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(file_names(path.parent().unwrap()), ["totp.safe"]);
+    }
+
+    #[test]
+    fn removes_the_temporary_file_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        // A directory can't be replaced by a file
+        let path = dir.path().join("totp.safe");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("content"), b"kept").unwrap();
+        assert!(write_file(&path, b"data").is_err());
+        assert_eq!(file_names(dir.path()), ["totp.safe"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_permissions_of_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        write_file(&path, b"first").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_file(&path, b"second").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,214 @@
+use super::atomic;
+use super::lock::FileLock;
+use super::{Database, DatabaseError};
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXTENSION: &str = "bak";
+
+/// Rotating copies of a database file, taken before it's overwritten
+#[derive(Debug, Clone)]
+pub struct Backups {
+    vault: PathBuf,
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// A single copy of the database file
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub created: SystemTime,
+}
+
+impl Backups {
+    /// Keeps up to `keep` copies of the `vault` file in `dir`. Zero disables backups.
+    pub fn new(vault: PathBuf, dir: PathBuf, keep: usize) -> Self {
+        Backups { vault, dir, keep }
+    }
+
+    /// Copies the current database file and removes copies beyond the limit.
+    /// Returns the path of the new copy, or `None` if there's nothing to back up.
+    /// No copy is made if the newest one has the same content, e.g. when a save is tried again
+    /// after failing, whose path is returned instead.
+    pub fn create(&self) -> Result<Option<PathBuf>, DatabaseError> {
+        let _lock = FileLock::shared(&self.vault)?;
+        self.copy()
+    }
+
+    fn copy(&self) -> Result<Option<PathBuf>, DatabaseError> {
+        if self.keep == 0 {
+            return Ok(None);
+        }
+        let data = match fs::read(&self.vault) {
+            Ok(d) => d,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(newest) = self.list()?.into_iter().next() {
+            if fs::read(&newest.path)? == data {
+                return Ok(Some(newest.path));
+            }
+        }
+        let mut millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut path = self.backup_path(millis);
+        while path.exists() {
+            millis += 1;
+            path = self.backup_path(millis);
+        }
+        atomic::write_file(&path, &data)?;
+        fs::set_permissions(&path, fs::metadata(&self.vault)?.permissions())?;
+        for old_backup in self.list()?.iter().skip(self.keep) {
+            fs::remove_file(&old_backup.path)?;
+        }
+        Ok(Some(path))
+    }
+
+    /// Returns the existing copies, newest first
+    pub fn list(&self) -> Result<Vec<Backup>, DatabaseError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let prefix = format!("{}.", self.vault_name());
+        let suffix = format!(".{}", EXTENSION);
+        let mut backups = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            let millis = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(&suffix))
+                .and_then(|millis| millis.parse().ok());
+            if let Some(millis) = millis {
+                let created = UNIX_EPOCH + Duration::from_millis(millis);
+                backups.push(Backup { path, created });
+            }
+        }
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+        Ok(backups)
+    }
+
+    /// Replaces the database file with a copy after checking that `database`,
+    /// opened on the copy, can read it. The replaced file is backed up first.
+    pub fn restore(&self, backup: &Backup, database: &dyn Database) -> Result<(), DatabaseError> {
+        database.get_applications()?;
+        let data = fs::read(&backup.path)?;
+        let _lock = FileLock::exclusive(&self.vault)?;
+        self.copy()?;
+        atomic::write_file(&self.vault, &data)?;
+        Ok(())
+    }
+
+    fn vault_name(&self) -> String {
+        self.vault
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn backup_path(&self, millis: u128) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}.{}", self.vault_name(), millis, EXTENSION))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{JsonDatabase, JsonDatabaseTrait};
+
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn backups(dir: &Path, keep: usize) -> Backups {
+        Backups::new(dir.join("totp.safe"), dir.join("backups"), keep)
+    }
+
+    fn contents(backups: &Backups) -> Vec<Vec<u8>> {
+        backups
+            .list()
+            .unwrap()
+            .iter()
+            .map(|backup| fs::read(&backup.path).unwrap())
+            .collect()
+    }
+
+    fn database(path: PathBuf, password: &str) -> JsonDatabase {
+        JsonDatabase::new(path, String::from(password))
+    }
+
+    #[test]
+    fn keeps_the_newest_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = backups(dir.path(), 2);
+        assert!(backups.create().unwrap().is_none());
+        assert!(backups.list().unwrap().is_empty());
+
+        for content in ["first", "second", "third"] {
+            fs::write(dir.path().join("totp.safe"), content).unwrap();
+            let path = backups.create().unwrap().unwrap();
+            assert_eq!(fs::read(path).unwrap(), content.as_bytes());
+        }
+        assert_eq!(contents(&backups), [&b"third"[..], b"second"]);
+    }
+
+    #[test]
+    fn skips_copies_of_unchanged_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = backups(dir.path(), 2);
+        fs::write(dir.path().join("totp.safe"), "first").unwrap();
+        backups.create().unwrap();
+        fs::write(dir.path().join("totp.safe"), "second").unwrap();
+        let path = backups.create().unwrap().unwrap();
+        // Saves failing after the backup leave the same content to back up again
+        assert_eq!(backups.create().unwrap().unwrap(), path);
+        assert_eq!(backups.create().unwrap().unwrap(), path);
+        assert_eq!(contents(&backups), [&b"second"[..], b"first"]);
+    }
+
+    #[test]
+    fn can_be_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("totp.safe"), "content").unwrap();
+        let backups = backups(dir.path(), 0);
+        assert!(backups.create().unwrap().is_none());
+        assert!(!dir.path().join("backups").exists());
+    }
+
+    #[test]
+    fn restores_a_copy_that_can_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("totp.safe");
+        let backups = backups(dir.path(), 5);
+        database(vault.clone(), "password")
+            .save_applications(&HashMap::new())
+            .unwrap();
+        let saved = fs::read(&vault).unwrap();
+        backups.create().unwrap();
+        fs::write(&vault, "damaged").unwrap();
+
+        let backup = &backups.list().unwrap()[0];
+        let wrong = database(backup.path.clone(), "wrong");
+        assert!(matches!(
+            backups.restore(backup, &wrong),
+            Err(DatabaseError::WrongPassword)
+        ));
+        assert_eq!(fs::read(&vault).unwrap(), b"damaged");
+        assert_eq!(backups.list().unwrap().len(), 1);
+
+        let right = database(backup.path.clone(), "password");
+        backups.restore(backup, &right).unwrap();
+        assert_eq!(fs::read(&vault).unwrap(), saved);
+        // The replaced file is kept too
+        assert_eq!(contents(&backups)[0], b"damaged");
+    }
+}
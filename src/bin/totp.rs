@@ -3,12 +3,21 @@ extern crate ctrlc;
 extern crate dirs;
 extern crate promkit;
 extern crate rustotpony;
+extern crate serde_json;
 
-use clap::{Parser, Subcommand};
+#[macro_use]
+extern crate serde_derive;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use promkit::preset::confirm::Confirm;
 use promkit::preset::password::Password;
 use rustotpony::*;
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -25,6 +34,18 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 By {author-with-newline}{after-help}\
 ")]
 struct Cli {
+    /// Profile to use instead of the default one
+    #[arg(long, global = true, env = "RUSTOTPONY_PROFILE")]
+    profile: Option<String>,
+    /// Database file [default: $XDG_DATA_HOME/rustotpony/totp.safe]
+    #[arg(long, global = true, env = "RUSTOTPONY_DB")]
+    db: Option<PathBuf>,
+    /// age identity file or SSH private key to unlock the database instead of a password
+    #[arg(long, global = true, env = "RUSTOTPONY_IDENTITY")]
+    identity: Option<PathBuf>,
+    /// File required along with the password to unlock the database
+    #[arg(long, global = true, env = "RUSTOTPONY_KEYFILE")]
+    keyfile: Option<PathBuf>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -56,12 +77,223 @@ enum Commands {
     },
     /// Delete all generators
     Eradicate {},
-    /// Export all generators as JSON
-    Export {},
+    /// Change the database password
+    Passwd {},
+    /// Set a new database password with the recovery key
+    Recover {},
+    /// Export generators
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// File to write to instead of stdout (json and pam formats)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Number of scratch codes to generate (pam format)
+        #[arg(long, default_value_t = 0)]
+        scratch_codes: usize,
+        #[command(flatten)]
+        pass: PassArgs,
+        /// Glob patterns to select generators by name (all generators if omitted)
+        patterns: Vec<String>,
+    },
+    /// Import generators
+    Import {
+        /// Input format
+        #[arg(short, long, value_enum, default_value_t = ImportFormat::Auto)]
+        format: ImportFormat,
+        /// Name of the imported generator (pam format)
+        #[arg(long)]
+        name: Option<String>,
+        /// Name of the user of the imported generator (pam format)
+        #[arg(long, default_value = "")]
+        username: String,
+        #[command(flatten)]
+        pass: PassArgs,
+        /// File to import from, `-` or nothing to read stdin (all formats but pass)
+        source: Option<PathBuf>,
+    },
+    /// Manage members of a database shared with age public keys
+    Member {
+        #[command(subcommand)]
+        command: MemberCommands,
+    },
+    /// Manage automatic backups of the database
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+    /// Manage profiles, each with its own database
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Convert the database to another storage format
+    Migrate {
+        /// Storage format to convert the database to
+        #[arg(value_enum)]
+        to: StorageFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemberCommands {
+    /// List public keys the database is shared with
+    List {},
+    /// Share the database with a public key and re-encrypt it
+    Add {
+        /// age (age1…) or SSH (ssh-ed25519 …, ssh-rsa …) public key
+        key: String,
+    },
+    /// Stop sharing the database with a public key and re-encrypt it
+    Remove {
+        /// Public key as shown by `totp member list`
+        key: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupCommands {
+    /// List backups, newest first
+    List {},
+    /// Replace the database with a backup
+    Restore {
+        /// Number of the backup as shown by `totp backup list`
+        number: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List profiles and their databases
+    List {},
+    /// Create a profile and its database
+    Create {
+        /// Name of the profile
+        name: String,
+    },
+    /// Delete a profile along with its database and backups
+    Remove {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+#[derive(Clone, PartialEq, ValueEnum)]
+enum StorageFormat {
+    /// A JSON document encrypted with age, the default
+    Json,
+    /// A JSON document encrypted with age to the public key of the identity given with --identity
+    Identity,
+    /// A SQLite database with encrypted secrets, names and usernames are stored in plain text
+    #[cfg(feature = "sqlite")]
+    Sqlite,
 }
 
+#[derive(Clone, ValueEnum)]
+enum ExportFormat {
+    /// RusTOTPony JSON printed to stdout
+    Json,
+    /// One GPG-encrypted otpauth URI per generator in a password store (pass-otp)
+    Pass,
+    /// A single generator as a ~/.google_authenticator file (libpam-google-authenticator)
+    Pam,
+}
+
+#[derive(Clone, ValueEnum)]
+enum ImportFormat {
+    /// Detect the format of the file: RusTOTPony JSON, otpauth URIs, Google Authenticator
+    /// migration, Aegis, andOTP or 2FAS backups
+    Auto,
+    /// Plain text with one otpauth URI per line
+    Otpauth,
+    /// GPG-encrypted otpauth URIs in a password store (pass-otp)
+    Pass,
+    /// A ~/.google_authenticator file (libpam-google-authenticator)
+    Pam,
+}
+
+#[derive(Args)]
+struct PassArgs {
+    /// Password store directory [default: $PASSWORD_STORE_DIR or ~/.password-store]
+    #[arg(long)]
+    store: Option<PathBuf>,
+    /// GnuPG binary used to decrypt and encrypt password store entries
+    #[arg(long, env = "RUSTOTPONY_GPG", default_value = "gpg")]
+    gpg: String,
+}
+
+/// Settings read from $XDG_CONFIG_HOME/rustotpony/config.json
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// Profile used when `--profile` isn't given, replaced with the one in use on start
+    profile: Option<String>,
+    /// Settings of the named profiles, the settings below are those of the default profile
+    profiles: HashMap<String, ProfileConfig>,
+    /// Database file, see `--db`
+    db: Option<PathBuf>,
+    backups: BackupConfig,
+    /// age identity file or SSH private key, see `--identity`
+    identity: Option<PathBuf>,
+    /// Key file required along with the password, see `--keyfile`
+    keyfile: Option<PathBuf>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+struct ProfileConfig {
+    /// Database file [default: profiles/<name>/totp.safe in the data directory]
+    db: Option<PathBuf>,
+    identity: Option<PathBuf>,
+    keyfile: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct BackupConfig {
+    /// Number of backups to keep, zero disables them
+    keep: usize,
+    /// Directory of the backups [default: `backups` next to the database]
+    dir: Option<PathBuf>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig { keep: 5, dir: None }
+    }
+}
+
+impl PassArgs {
+    fn password_store(&self) -> PasswordStore {
+        let dir = self
+            .store
+            .clone()
+            .unwrap_or_else(PasswordStore::default_dir);
+        PasswordStore::new(dir, self.gpg.clone())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
 fn main() {
     let cli = Cli::parse();
+    // A database given explicitly doesn't need the default one
+    if cli.db.is_none() {
+        migrate_legacy_dir();
+    }
+    let config = resolve_config(&cli, load_config());
+    // The key file of a password-protected database is still needed to convert it to an identity
+    let to_identity = matches!(
+        &cli.command,
+        Some(Commands::Migrate {
+            to: StorageFormat::Identity
+        })
+    );
+    if config.identity.is_some() && config.keyfile.is_some() && !to_identity {
+        exit_with_error("A key file can only be used with a password, not with an identity");
+    }
+    CONFIG.get_or_init(|| config);
 
     match &cli.command {
         Some(Commands::Dash {}) => {
@@ -82,8 +314,48 @@ fn main() {
         Some(Commands::Eradicate {}) => {
             eradicate_database();
         }
-        Some(Commands::Export {}) => {
-            export_database();
+        Some(Commands::Passwd {}) => {
+            change_password();
+        }
+        Some(Commands::Recover {}) => {
+            recover_password();
+        }
+        Some(Commands::Export {
+            format,
+            output,
+            scratch_codes,
+            pass,
+            patterns,
+        }) => {
+            export_database(format, output.as_deref(), *scratch_codes, pass, patterns);
+        }
+        Some(Commands::Import {
+            format,
+            name,
+            username,
+            pass,
+            source,
+        }) => {
+            import_applications(format, name.as_deref(), username, pass, source.as_deref());
+        }
+        Some(Commands::Member { command }) => {
+            manage_members(command);
+        }
+        Some(Commands::Backup {
+            command: BackupCommands::List {},
+        }) => {
+            list_backups();
+        }
+        Some(Commands::Backup {
+            command: BackupCommands::Restore { number },
+        }) => {
+            restore_backup(*number);
+        }
+        Some(Commands::Profile { command }) => {
+            manage_profiles(command);
+        }
+        Some(Commands::Migrate { to }) => {
+            migrate_database(to);
         }
         _ => {
             show_dashboard();
@@ -91,77 +363,505 @@ fn main() {
     }
 }
 
-fn app() -> RusTOTPony<JsonDatabase> {
-    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    let old_path = home.join(Path::new(".rustotpony/db.json"));
-    let new_path = home.join(Path::new(".rustotpony/totp.safe"));
-    let secret = get_secret();
-    let new_db = JsonDatabase::new(new_path.clone(), secret.clone());
+/// Directory used before RusTOTPony followed the XDG base directories
+fn legacy_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".rustotpony")
+}
+
+/// Files that older versions kept in the legacy directory: the database and the one before 0.5
+const LEGACY_FILES: &[&str] = &["totp.safe", "db.json"];
+
+/// Directory of the database, the legacy one as long as a database is left there
+fn data_dir() -> PathBuf {
+    let legacy_dir = legacy_dir();
+    match dirs::data_dir() {
+        Some(dir)
+            if !LEGACY_FILES
+                .iter()
+                .any(|file| legacy_dir.join(file).exists()) =>
+        {
+            dir.join("rustotpony")
+        }
+        _ => legacy_dir,
+    }
+}
+
+/// Directory of the config file
+fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("rustotpony"))
+        .unwrap_or_else(legacy_dir)
+}
+
+/// Moves the databases of the legacy directory to the XDG data directory.
+/// The legacy directory keeps being used if they can't be moved.
+fn migrate_legacy_dir() {
+    if let Some(data_dir) = dirs::data_dir() {
+        move_legacy_files(&legacy_dir(), &data_dir.join("rustotpony"));
+    }
+}
+
+/// Moves the files older versions created, leaving anything else in the legacy directory.
+/// Nothing is moved if any of them is in the data directory already.
+fn move_legacy_files(legacy_dir: &Path, data_dir: &Path) {
+    let files: Vec<_> = LEGACY_FILES
+        .iter()
+        .filter(|file| legacy_dir.join(file).exists())
+        .collect();
+    if files.is_empty() || files.iter().any(|file| data_dir.join(file).exists()) {
+        return;
+    }
+    if let Err(err) = std::fs::create_dir_all(data_dir) {
+        eprintln!("Couldn't create {}: {}", data_dir.display(), err);
+        return;
+    }
+    for file in files {
+        let (from, to) = (legacy_dir.join(file), data_dir.join(file));
+        if let Err(err) = std::fs::rename(&from, &to) {
+            eprintln!(
+                "Couldn't move {} to {}: {}",
+                from.display(),
+                to.display(),
+                err
+            );
+            return;
+        }
+        eprintln!("Moved {} to {}", from.display(), to.display());
+    }
+    // Only removed if nothing else is left in it
+    let _ = std::fs::remove_dir(legacy_dir);
+}
+
+/// Name of the profile that uses the database in the data directory
+const DEFAULT_PROFILE: &str = "default";
+
+fn profile_dir(name: &str) -> PathBuf {
+    data_dir().join("profiles").join(name)
+}
+
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Database file of a profile, the settings of the default profile are at the top of the config
+fn profile_vault_path(config: &Config, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        return config
+            .db
+            .clone()
+            .unwrap_or_else(|| data_dir().join("totp.safe"));
+    }
+    config
+        .profiles
+        .get(name)
+        .and_then(|profile| profile.db.clone())
+        .unwrap_or_else(|| profile_dir(name).join("totp.safe"))
+}
+
+/// Replaces the settings of the default profile with those of a named profile.
+/// Unless it's being created, the profile must be set in the config or have a directory.
+fn use_profile(config: &mut Config, name: &str, create: bool) {
+    if name == DEFAULT_PROFILE {
+        config.profile = None;
+        return;
+    }
+    if !is_valid_profile_name(name) {
+        exit_with_error(format!("Invalid profile name '{}'", name));
+    }
+    if !create && !config.profiles.contains_key(name) && !profile_dir(name).exists() {
+        exit_with_error(format!(
+            "Profile '{}' doesn't exist, create it with `totp profile create {}`",
+            name, name
+        ));
+    }
+    let settings = config.profiles.get(name).cloned().unwrap_or_default();
+    config.db = Some(profile_vault_path(config, name));
+    config.identity = settings.identity;
+    config.keyfile = settings.keyfile;
+    config.profile = Some(String::from(name));
+}
+
+fn profile_name() -> &'static str {
+    config().profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+}
+
+fn vault_path() -> PathBuf {
+    config()
+        .db
+        .clone()
+        .unwrap_or_else(|| data_dir().join("totp.safe"))
+}
+
+/// Database written by versions before 0.5, looked for only next to the default database
+fn legacy_db_path() -> Option<PathBuf> {
+    match config().db {
+        Some(_) => None,
+        None => Some(data_dir().join("db.json")),
+    }
+}
+
+/// Applies the profile, then the options given on the command line or in the environment,
+/// to the settings of the config file
+fn resolve_config(cli: &Cli, mut config: Config) -> Config {
+    // A new profile is used right away to create its database
+    match &cli.command {
+        Some(Commands::Profile {
+            command: ProfileCommands::Create { name },
+        }) => use_profile(&mut config, name, true),
+        _ => {
+            if let Some(name) = cli.profile.clone().or_else(|| config.profile.clone()) {
+                use_profile(&mut config, &name, false);
+            }
+        }
+    }
+    if cli.db.is_some() {
+        config.db = cli.db.clone();
+    }
+    if cli.identity.is_some() {
+        config.identity = cli.identity.clone();
+    }
+    if cli.keyfile.is_some() {
+        config.keyfile = cli.keyfile.clone();
+    }
+    config
+}
+
+fn load_config() -> Config {
+    let path = config_dir().join("config.json");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+            exit_with_error(format!("Couldn't read {}: {}", path.display(), err))
+        }),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(err) => exit_with_error(format!("Couldn't read {}: {}", path.display(), err)),
+    }
+}
+
+fn config() -> &'static Config {
+    CONFIG.get().expect("The config is loaded on start")
+}
+
+fn backups() -> Backups {
+    let config = &config().backups;
+    // Backups of the named profiles are kept apart when they all go to the same directory
+    let dir = match (&config.dir, profile_name()) {
+        (Some(dir), DEFAULT_PROFILE) => dir.clone(),
+        (Some(dir), name) => dir.join(name),
+        (None, _) => vault_path().with_file_name("backups"),
+    };
+    Backups::new(vault_path(), dir, config.keep)
+}
+
+/// Opens a database file with the identity or the password, whichever is configured
+fn open_vault(path: PathBuf) -> Box<dyn Database> {
+    match &config().identity {
+        Some(identity) => Box::new(AgeIdentityDatabase::new(path, identity.clone())),
+        None => {
+            let secret = combine_key_file(key_file().as_ref(), &get_secret());
+            open_database(path, secret).unwrap_or_else(|err| exit_with_error(err))
+        }
+    }
+}
+
+fn key_file() -> Option<KeyFile> {
+    config()
+        .keyfile
+        .as_ref()
+        .map(|path| KeyFile::read(path).unwrap_or_else(|err| exit_with_error(err)))
+}
+
+/// Make sure the key file is given and is the right one if the database needs it
+fn check_key_file(keyfile: Option<&KeyFile>, vault: &Path) {
+    if !KeyFile::is_required(vault) {
+        return;
+    }
+    match keyfile {
+        Some(keyfile) => keyfile
+            .check(vault)
+            .unwrap_or_else(|err| exit_with_error(err)),
+        None => exit_with_error(
+            "The database requires a key file, \
+            pass it with --keyfile or set `keyfile` in the config",
+        ),
+    }
+}
+
+fn combine_key_file(keyfile: Option<&KeyFile>, password: &str) -> String {
+    match keyfile {
+        Some(keyfile) => keyfile.combine(password),
+        None => String::from(password),
+    }
+}
+
+/// Password of a database that doesn't exist yet, a recovery key is offered once it's saved
+static NEW_VAULT_SECRET: OnceLock<String> = OnceLock::new();
+
+fn app() -> RusTOTPony<Box<dyn Database>> {
+    if let Some(identity) = &config().identity {
+        return identity_app(identity);
+    }
+    let old_path = legacy_db_path().filter(|path| path.exists());
+    let new_path = vault_path();
+    let format = VaultFormat::detect_file(&new_path).unwrap_or_else(|err| exit_with_error(err));
+    if format == Some(VaultFormat::AgeRecipients) {
+        exit_with_error(
+            "The database is encrypted with age public keys, \
+            pass an identity file with --identity or set `identity` in the config",
+        );
+    }
+    let keyfile = key_file();
+    check_key_file(keyfile.as_ref(), &new_path);
+    let password = get_secret();
+    let secret = combine_key_file(keyfile.as_ref(), &password);
+    let new_db = AgeJsonDatabase::new(new_path.clone(), secret.clone());
+    // If the database is still encrypted with AES, re-encrypt it with age
+    // and keep the AES-encrypted file as a backup
+    if format == Some(VaultFormat::Aes) {
+        let backup_path = sidecar_path(&new_path, "aes.bak");
+        eprintln!("Migrating the database to age encryption…");
+        let apps = open_database(new_path.clone(), password.clone())
+            .and_then(|old_db| old_db.get_applications())
+            .unwrap_or_else(|err| exit_with_error(err));
+        if let Err(err) = std::fs::copy(&new_path, &backup_path) {
+            exit_with_error(format!("Couldn't back up the database: {}", err));
+        }
+        save_migrated_applications(&new_db, &apps);
+        eprintln!("Database migrated successfully to age encryption.");
+        eprintln!(
+            "The AES-encrypted database is kept at: {}",
+            backup_path.display()
+        );
+    }
+    if let Some(keyfile) = &keyfile {
+        if !KeyFile::is_required(&new_path) {
+            protect_with_key_file(keyfile, &new_path, &password, &secret, format);
+        }
+    }
+    if format == Some(VaultFormat::Sqlite) {
+        return open_app(
+            open_database(new_path, secret).unwrap_or_else(|err| exit_with_error(err)),
+        );
+    }
     // If old database exists, migrate it to the new format
     // and notify the user about the change
-    if old_path.exists() {
+    if let Some(old_path) = &old_path {
         // If the new database already exists, abort the migration and notify the user
         if new_path.exists() {
-            println!("Both old and new databases found, using the new one…");
-            println!("Please remove the old database at: {}", old_path.display());
-            return RusTOTPony::new(new_db);
-        }
-        println!();
-        println!("IMPORTANT:");
-        println!("    RusTOTPony has changed the database format.");
-        println!("    The old database will be migrated to the new format and kept as a backup.");
-        println!("      old: {}", old_path.display());
-        println!("      new: {}", new_path.display());
-        println!("    If this is not what you want, you can rollback to the old version (0.4.2) and remove the new database.");
-        println!();
-        println!("Migrating old database to the new format…");
-        let old_db = JsonDatabase::new(old_path.clone(), secret);
-        let apps = old_db.get_applications();
-        new_db.save_applications(&apps);
-        println!("Old database migrated successfully to the new format.");
-        println!("Please remove the old database at: {}", old_path.display());
-    }
-    RusTOTPony::new(new_db)
+            eprintln!("Both old and new databases found, using the new one…");
+            eprintln!("Please remove the old database at: {}", old_path.display());
+            return open_app(Box::new(new_db));
+        }
+        eprintln!();
+        eprintln!("IMPORTANT:");
+        eprintln!("    RusTOTPony has changed the database format.");
+        eprintln!("    The old database will be migrated to the new format and kept as a backup.");
+        eprintln!("      old: {}", old_path.display());
+        eprintln!("      new: {}", new_path.display());
+        eprintln!("    If this is not what you want, you can rollback to the old version (0.4.2) and remove the new database.");
+        eprintln!();
+        eprintln!("Migrating old database to the new format…");
+        let old_db = JsonDatabase::new(old_path.clone(), password.clone());
+        let apps = old_db
+            .get_applications()
+            .unwrap_or_else(|err| exit_with_error(err));
+        save_migrated_applications(&new_db, &apps);
+        eprintln!("Old database migrated successfully to the new format.");
+        eprintln!("Please remove the old database at: {}", old_path.display());
+    }
+    if format.is_none() && old_path.is_none() && !Recovery::for_vault(&new_path).exists() {
+        let _ = NEW_VAULT_SECRET.set(secret);
+    }
+    open_app(Box::new(new_db))
 }
 
-fn get_secret() -> String {
-    let mut p = Password::default()
-        .title("Enter your database password")
+/// Re-encrypt an existing database with the password combined with the key file
+/// and mark it as needing the key file from now on, along with its recovery key.
+/// Databases just migrated from AES are already encrypted with the key file.
+fn protect_with_key_file(
+    keyfile: &KeyFile,
+    path: &Path,
+    password: &str,
+    secret: &str,
+    format: Option<VaultFormat>,
+) {
+    let protected = matches!(format, Some(VaultFormat::Age) | Some(VaultFormat::Sqlite))
+        && keyfile
+            .unlocks(path, password)
+            .unwrap_or_else(|err| exit_with_error(err));
+    if protected {
+        // Only its fingerprint was lost, the database is encrypted with the key file already
+        eprintln!("The database is protected with the key file, restoring its fingerprint…");
+        if let Err(err) = keyfile.save_fingerprint(path) {
+            exit_with_error(err);
+        }
+        return;
+    }
+    if matches!(format, Some(VaultFormat::Age) | Some(VaultFormat::Sqlite)) {
+        eprintln!("Protecting the database with the key file…");
+        let mut db = open_database(path.to_path_buf(), String::from(password))
+            .unwrap_or_else(|err| exit_with_error(err));
+        let apps = db
+            .get_applications()
+            .unwrap_or_else(|err| exit_with_error(err));
+        match backups().create() {
+            Ok(Some(backup_path)) => {
+                eprintln!(
+                    "The database without the key file is kept at: {}",
+                    backup_path.display()
+                );
+                eprintln!(
+                    "Restore it with `totp backup restore` without the key file to stop using it"
+                );
+            }
+            Ok(None) => {}
+            Err(err) => exit_with_error(format!("Couldn't back up the database: {}", err)),
+        }
+        if let Err(err) = db.set_secret(String::from(secret)) {
+            exit_with_error(err);
+        }
+        save_migrated_applications(db.as_ref(), &apps);
+    }
+    if let Err(err) = keyfile.save_fingerprint(path) {
+        exit_with_error(err);
+    }
+    update_recovery_key(path, secret, "The database is protected with the key file");
+}
+
+/// Create a recovery key for a new database if the user wants one
+fn offer_recovery_key(recovery: &Recovery, secret: &str) {
+    let mut p = Confirm::new("Create a recovery key in case you forget the password?")
         .prompt()
         .unwrap();
+    if !matches!(p.run().unwrap().as_str(), "y" | "Y" | "yes") {
+        return;
+    }
+    match recovery.create(secret) {
+        Ok(key) => {
+            println!("Your recovery key, store it somewhere safe, it won't be shown again:");
+            println!();
+            println!("    {}", key);
+            println!();
+            println!("Use it with `totp recover` to set a new password.");
+        }
+        Err(err) => exit_with_error(format!("Couldn't create the recovery key: {}", err)),
+    }
+}
+
+fn identity_app(identity: &Path) -> RusTOTPony<Box<dyn Database>> {
+    let path = vault_path();
+    match VaultFormat::detect_file(&path) {
+        Ok(Some(VaultFormat::AgeRecipients)) | Ok(None) => {}
+        Ok(Some(found)) => exit_with_error(format!(
+            "{}, convert it with `totp migrate identity`",
+            DatabaseError::FormatMismatch {
+                expected: VaultFormat::AgeRecipients,
+                found,
+            }
+        )),
+        Err(err) => exit_with_error(err),
+    }
+    open_app(Box::new(AgeIdentityDatabase::new(
+        path,
+        identity.to_path_buf(),
+    )))
+}
+
+fn open_app(db: Box<dyn Database>) -> RusTOTPony<Box<dyn Database>> {
+    RusTOTPony::new(db)
+        .unwrap_or_else(|err| match err {
+            // The fingerprint telling that a key file is needed may have been lost
+            DatabaseError::WrongPassword if config().keyfile.is_none() => exit_with_error(
+                "Couldn't open the database: Wrong password, \
+                or the database needs a key file, pass it with --keyfile",
+            ),
+            err => exit_with_error(format!("Couldn't open the database: {}", err)),
+        })
+        .with_backups(backups())
+}
+
+fn save_migrated_applications(db: &dyn Database, apps: &HashMap<String, GenApp>) {
+    if let Err(err) = db.save_applications(apps) {
+        exit_with_error(format!("Couldn't save the migrated database: {}", err));
+    }
+}
+
+fn save_app(app: &RusTOTPony<Box<dyn Database>>) {
+    check_saved(app.flush());
+}
+
+/// Saves every application even if none of them changed, e.g. to create the file of a new database
+fn save_all_apps(app: &RusTOTPony<Box<dyn Database>>) {
+    check_saved(app.flush_all());
+}
+
+fn check_saved(result: Result<(), DatabaseError>) {
+    if let Err(err) = result {
+        exit_with_error(format!("Couldn't save the database: {}", err));
+    }
+    // Only now the database holds the password, opening it may not have saved anything
+    if let Some(secret) = NEW_VAULT_SECRET.get() {
+        let recovery = Recovery::for_vault(&vault_path());
+        if !recovery.exists() {
+            offer_recovery_key(&recovery, secret);
+        }
+    }
+}
+
+fn exit_with_error(message: impl Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Asks for the database password once, later calls return the same one
+fn get_secret() -> String {
+    static PASSWORD: OnceLock<String> = OnceLock::new();
+    PASSWORD
+        .get_or_init(|| prompt_password("Enter your database password"))
+        .clone()
+}
+
+fn prompt_password(title: &str) -> String {
+    let mut p = Password::default().title(title).prompt().unwrap();
     p.run().unwrap()
 }
 
 fn show_dashboard() {
-    match app().get_applications() {
-        Ok(apps) => {
-            let mut is_first_iteration = true;
-            let lines_count = apps.len() + 1;
-            println!("Welcome to RusTOTPony realtime dashboard! Press ^C to quit.");
-            ctrlc::set_handler(move || {
-                print!("\x1B[{}A\x1B[0G\x1B[0J", lines_count + 1);
-                println!("I won't tell anyone about this 🤫");
-                std::process::exit(0);
-            })
-            .expect("Error setting Ctrl-C handler");
-            // Prepare sorted keys for displaying apps in order
-            let mut keys: Vec<String> = apps.keys().cloned().collect();
-            keys.sort();
-            loop {
-                if is_first_iteration {
-                    is_first_iteration = false;
-                } else {
-                    print!("\x1B[{}A", lines_count);
-                }
-                print_progress_bar();
-                for key in keys.iter() {
-                    let app = &apps[key];
-                    println! {"{} {}", app.get_code(), app.get_name()};
-                }
-                thread::sleep(Duration::from_millis(100));
-            }
+    let app = app();
+    let apps = app.get_applications();
+    if apps.is_empty() {
+        println!("There are no applications");
+        return;
+    }
+    let mut is_first_iteration = true;
+    let lines_count = apps.len() + 1;
+    println!("Welcome to RusTOTPony realtime dashboard! Press ^C to quit.");
+    ctrlc::set_handler(move || {
+        print!("\x1B[{}A\x1B[0G\x1B[0J", lines_count + 1);
+        println!("I won't tell anyone about this 🤫");
+        std::process::exit(0);
+    })
+    .expect("Error setting Ctrl-C handler");
+    // Prepare sorted keys for displaying apps in order
+    let mut keys: Vec<String> = apps.keys().cloned().collect();
+    keys.sort();
+    loop {
+        if is_first_iteration {
+            is_first_iteration = false;
+        } else {
+            print!("\x1B[{}A", lines_count);
+        }
+        print_progress_bar();
+        for key in keys.iter() {
+            let app = &apps[key];
+            println! {"{} {}", app.get_code(), app.get_name()};
         }
-        Err(err) => println!("{}", err),
+        thread::sleep(Duration::from_millis(100));
     }
 }
 
@@ -181,13 +881,11 @@ fn show_applications_list(_: bool) {
     let app = app();
     let mut output_table: HashMap<&str, Vec<&str>> = HashMap::new();
     let mut applications_count = 0;
-    let apps = match app.get_applications() {
-        Ok(v) => v,
-        Err(e) => {
-            println!("{}", e);
-            return;
-        }
-    };
+    let apps = app.get_applications();
+    if apps.is_empty() {
+        println!("There are no applications");
+        return;
+    }
     for application in apps.values() {
         applications_count += 1;
         output_table
@@ -231,10 +929,11 @@ fn show_applications_list(_: bool) {
     );
     println!("{}", header_row_delimiter);
 
-    for i in 0..applications_count {
-        let name = &output_table["name"][i];
-        let key = &output_table["key"][i];
-        let username = &output_table["username"][i];
+    let rows = output_table["name"]
+        .iter()
+        .zip(output_table["key"].iter())
+        .zip(output_table["username"].iter());
+    for ((name, key), username) in rows.take(applications_count) {
         println!(
             "| {name:<name_width$} | {key: <key_width$} | {username: <username_width$} |",
             name_width = name_max_length,
@@ -257,7 +956,7 @@ fn create_application(name: &str, username: &str) {
     let mut app = app();
     match app.create_application(name, username, &secret) {
         Ok(_) => {
-            app.flush();
+            save_app(&app);
             println!("New application created: {}", name)
         }
         Err(err) => println!("{} Aborting…", err),
@@ -268,9 +967,13 @@ fn delete_application(name: &str) {
     let mut app = app();
     match app.delete_application(name) {
         Ok(_) => {
-            app.flush();
+            save_app(&app);
             println!("Application '{}' successfully deleted", name)
         }
+        Err(ApplicationError::NotFound(_)) => println!(
+            "Couldn't delete application '{}': Application with the name '{}' doesn't exist",
+            name, name
+        ),
         Err(err) => println!("Couldn't delete application '{}': {}", name, err),
     };
 }
@@ -279,7 +982,7 @@ fn rename_application(name: &str, newname: &str) {
     let mut app = app();
     match app.rename_application(name, newname) {
         Ok(_) => {
-            app.flush();
+            save_app(&app);
             println!(
                 "Application '{}' successfully renamed to '{}'",
                 name, newname
@@ -292,20 +995,757 @@ fn rename_application(name: &str, newname: &str) {
 fn eradicate_database() {
     let mut app = app();
     app.delete_all_applications();
-    app.flush();
+    save_app(&app);
     println!("Done.");
 }
 
-/// Export database as JSON
-fn export_database() {
+fn change_password() {
+    if config().identity.is_some() {
+        exit_with_error("The database is unlocked with an identity and has no password");
+    }
+    set_new_password(app());
+}
+
+fn recover_password() {
+    if config().identity.is_some() {
+        exit_with_error("The database is unlocked with an identity and has no password");
+    }
+    let recovery = Recovery::for_vault(&vault_path());
+    if !recovery.exists() {
+        exit_with_error("There's no recovery key for this database");
+    }
+    let key = prompt_password("Enter your recovery key");
+    let secret = recovery
+        .recover_secret(&key)
+        .unwrap_or_else(|err| exit_with_error(format!("Couldn't recover the password: {}", err)));
+    let db = open_database(vault_path(), secret).unwrap_or_else(|err| exit_with_error(err));
+    set_new_password(open_app(db));
+}
+
+/// Re-encrypt the database with a new password and keep the recovery key working
+fn set_new_password(mut app: RusTOTPony<Box<dyn Database>>) {
+    let secret = prompt_password("Enter the new password");
+    if prompt_password("Repeat the new password") != secret {
+        exit_with_error("Passwords don't match, the password wasn't changed");
+    }
+    // Rotating backups keep the previous file, unless they're disabled
+    if config().backups.keep == 0 && vault_path().exists() {
+        let backup_path = sidecar_path(&vault_path(), "bak");
+        if let Err(err) = std::fs::copy(vault_path(), &backup_path) {
+            exit_with_error(format!("Couldn't back up the database: {}", err));
+        }
+        println!(
+            "The previous database is kept at: {}",
+            backup_path.display()
+        );
+    }
+    let keyfile = key_file();
+    let secret = combine_key_file(keyfile.as_ref(), &secret);
+    if let Err(err) = app.change_password(secret.clone()) {
+        exit_with_error(format!("Couldn't change the password: {}", err));
+    }
+    // Without a key file, e.g. when it's lost and the password is recovered, it's not needed anymore
+    let marked = match &keyfile {
+        Some(keyfile) => keyfile.save_fingerprint(&vault_path()),
+        None => KeyFile::remove_fingerprint(&vault_path()),
+    };
+    if let Err(err) = marked {
+        exit_with_error(err);
+    }
+    update_recovery_key(&vault_path(), &secret, "The password was changed");
+    println!("Password changed");
+}
+
+/// Keep the recovery key of the database, if it has one, recovering the new secret
+fn update_recovery_key(vault: &Path, secret: &str, done: &str) {
+    let recovery = Recovery::for_vault(vault);
+    if recovery.exists() {
+        if let Err(err) = recovery.update(secret) {
+            exit_with_error(format!(
+                "{}, but the recovery key couldn't be updated: {}",
+                done, err
+            ));
+        }
+    }
+}
+
+/// Export database in the given format
+fn export_database(
+    format: &ExportFormat,
+    output: Option<&Path>,
+    scratch_codes: usize,
+    pass: &PassArgs,
+    patterns: &[String],
+) {
     let app = app();
-    let apps = match app.get_applications() {
+    let apps = match app.get_applications_matching(patterns) {
+        Ok(v) if v.is_empty() => {
+            eprintln!("There are no matching applications");
+            return;
+        }
+        Ok(v) => v,
+        Err(e) => exit_with_error(e),
+    };
+    match format {
+        ExportFormat::Json => {
+            let json = serde_json::to_string_pretty(&apps).unwrap();
+            write_output(output, &format!("{}\n", json));
+        }
+        ExportFormat::Pass => {
+            let store = pass.password_store();
+            let mut names: Vec<&str> = apps.keys().copied().collect();
+            names.sort();
+            for name in names {
+                match store.write_application(apps[name]) {
+                    Ok(_) => println!("Exported '{}'", name),
+                    Err(err) => println!("Couldn't export '{}': {}", name, err),
+                }
+            }
+        }
+        ExportFormat::Pam => {
+            if apps.len() != 1 {
+                exit_with_error(format!(
+                    "The pam format holds a single generator, but {} match. Aborting…",
+                    apps.len()
+                ));
+            }
+            let content = apps
+                .values()
+                .next()
+                .unwrap()
+                .to_google_authenticator(scratch_codes);
+            write_output(output, &content);
+        }
+    }
+}
+
+/// Print content to stdout or write it to a file readable by the owner only
+fn write_output(output: Option<&Path>, content: &str) {
+    let path = match output {
+        Some(path) => path,
+        None => {
+            print!("{}", content);
+            return;
+        }
+    };
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+    {
+        Ok(_) => println!("Exported to {}", path.display()),
+        Err(err) => println!("Couldn't write {}: {}", path.display(), err),
+    }
+}
+
+fn import_applications(
+    format: &ImportFormat,
+    name: Option<&str>,
+    username: &str,
+    pass: &PassArgs,
+    source: Option<&Path>,
+) {
+    let entries = match format {
+        ImportFormat::Auto => read_backup(source),
+        ImportFormat::Otpauth => {
+            read_source(source).and_then(|c| BackupFormat::OtpauthUris.parse(&c))
+        }
+        ImportFormat::Pass => pass.password_store().read_applications(),
+        ImportFormat::Pam => read_google_authenticator(name, username, source),
+    };
+    let entries = match entries {
         Ok(v) => v,
         Err(e) => {
             println!("{}", e);
             return;
         }
     };
-    let json = serde_json::to_string_pretty(&apps).unwrap();
-    println!("{}", json);
+    let mut app = app();
+    let total = entries.len();
+    let mut imported = 0;
+    for (name, entry) in entries {
+        match entry.and_then(|new_app| app.add_application(new_app).map_err(|e| e.to_string())) {
+            Ok(_) => {
+                imported += 1;
+                println!("Imported '{}'", name);
+            }
+            Err(err) => println!("Skipped '{}': {}", name, err),
+        }
+    }
+    if imported > 0 {
+        save_app(&app);
+    }
+    println!("{} of {} entries imported", imported, total);
+}
+
+fn read_backup(source: Option<&Path>) -> Result<Vec<ImportedEntry>, String> {
+    let content = read_source(source)?;
+    let (format, entries) = parse_backup(&content)?;
+    println!("Detected format: {}", format);
+    Ok(entries)
+}
+
+fn read_source(source: Option<&Path>) -> Result<String, String> {
+    match source {
+        Some(path) if path != Path::new("-") => std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e)),
+        _ => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .map_err(|e| format!("Couldn't read stdin: {}", e))?;
+            Ok(content)
+        }
+    }
+}
+
+fn read_google_authenticator(
+    name: Option<&str>,
+    username: &str,
+    source: Option<&Path>,
+) -> Result<Vec<ImportedEntry>, String> {
+    let name = name.ok_or("A name for the generator is required, use --name")?;
+    let content = read_source(source)?;
+    let entry = GenApp::from_google_authenticator(name, username, &content);
+    Ok(vec![(String::from(name), entry)])
+}
+
+fn manage_members(command: &MemberCommands) {
+    let Some(identity) = &config().identity else {
+        exit_with_error(
+            "Shared databases are unlocked with age identities, \
+            pass an identity file with --identity or set `identity` in the config",
+        );
+    };
+    let own_db = AgeIdentityDatabase::new(vault_path(), identity.clone());
+    let recipients = RecipientsFile::for_vault(&vault_path());
+    match command {
+        MemberCommands::List {} => {
+            let members = recipients.read().unwrap_or_else(|err| exit_with_error(err));
+            if members.is_empty() {
+                println!("The database isn't shared with anyone");
+            }
+            for member in members {
+                match own_db.is_own_key(&member) {
+                    Ok(true) => println!("{} (you)", member),
+                    _ => println!("{}", member),
+                }
+            }
+        }
+        MemberCommands::Add { key } => {
+            if change_members(&recipients, &own_db, |r| r.add(key)) {
+                println!("The database is shared with the key and re-encrypted");
+            } else {
+                println!("The database is already shared with this key");
+            }
+        }
+        MemberCommands::Remove { key } => {
+            if own_db
+                .is_own_key(key)
+                .unwrap_or_else(|err| exit_with_error(err))
+            {
+                exit_with_error(
+                    "The database is always encrypted to your own key, \
+                    another member has to remove it",
+                );
+            }
+            if change_members(&recipients, &own_db, |r| r.remove(key)) {
+                println!("The key is removed and the database re-encrypted");
+            } else {
+                exit_with_error("The database isn't shared with this key");
+            }
+        }
+    }
+}
+
+/// Changes the list of members and re-encrypts the database to them.
+/// The keys of the identity are listed too, so that other members keep the database encrypted to them.
+/// The previous list is put back if the database can't be saved,
+/// so that it keeps telling who the database is encrypted to.
+fn change_members(
+    recipients: &RecipientsFile,
+    own_db: &AgeIdentityDatabase,
+    change: impl FnOnce(&RecipientsFile) -> Result<bool, DatabaseError>,
+) -> bool {
+    // Make sure the database can be opened before changing who it's encrypted to
+    let app = app();
+    let path = recipients.get_path();
+    let previous = match std::fs::read(path) {
+        Ok(content) => Some(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => exit_with_error(format!("Couldn't read {}: {}", path.display(), err)),
+    };
+    if !change(recipients).unwrap_or_else(|err| exit_with_error(err)) {
+        return false;
+    }
+    let own_keys = own_db.public_keys().and_then(|keys| {
+        keys.iter()
+            .try_for_each(|key| recipients.add(key).map(|_| ()))
+    });
+    if let Err(err) = own_keys {
+        restore_members(path, previous);
+        exit_with_error(err);
+    }
+    if let Err(err) = app.flush_all() {
+        restore_members(path, previous);
+        exit_with_error(format!("Couldn't save the database: {}", err));
+    }
+    true
+}
+
+/// Puts back the content of the recipients file, removing it if there was none
+fn restore_members(path: &Path, previous: Option<Vec<u8>>) {
+    let restored = match previous {
+        Some(content) => std::fs::write(path, content),
+        None => std::fs::remove_file(path),
+    };
+    if let Err(err) = restored {
+        println!(
+            "Couldn't put back the previous members in {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+/// Rewrites the database in another storage format
+/// and keeps the previous file next to it as a backup
+fn migrate_database(to: &StorageFormat) {
+    match to {
+        StorageFormat::Json => migrate_password_database(VaultFormat::Age),
+        StorageFormat::Identity => migrate_to_identity(),
+        #[cfg(feature = "sqlite")]
+        StorageFormat::Sqlite => migrate_password_database(VaultFormat::Sqlite),
+    }
+}
+
+fn migrate_password_database(target: VaultFormat) {
+    if config().identity.is_some() {
+        exit_with_error("Only password-protected databases can be migrated");
+    }
+    let path = vault_path();
+    if !path.exists() {
+        exit_with_error("There's no database to migrate yet");
+    }
+    // Opening the database upgrades it from AES first
+    let app = app();
+    let secret = combine_key_file(key_file().as_ref(), &get_secret());
+    if VaultFormat::detect_file(&path).unwrap_or_else(|err| exit_with_error(err)) == Some(target) {
+        println!("The database is already encrypted with {}", target);
+        return;
+    }
+    let extension = match target {
+        VaultFormat::Sqlite => "json.bak",
+        _ => "sqlite.bak",
+    };
+    let backup_path = sidecar_path(&path, extension);
+    if let Err(err) = std::fs::copy(&path, &backup_path) {
+        exit_with_error(format!("Couldn't back up the database: {}", err));
+    }
+    let apps = app.get_applications();
+    match target {
+        #[cfg(feature = "sqlite")]
+        VaultFormat::Sqlite => {
+            // The SQLite database is written aside and moved over the JSON file once complete
+            let new_path = sidecar_path(&path, "migrating");
+            if let Err(err) = std::fs::remove_file(&new_path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    exit_with_error(format!("Couldn't remove {}: {}", new_path.display(), err));
+                }
+            }
+            save_migrated_applications(&SqliteDatabase::new(new_path.clone(), secret), apps);
+            if let Err(err) = std::fs::rename(&new_path, &path) {
+                exit_with_error(format!("Couldn't replace the database: {}", err));
+            }
+        }
+        _ => save_migrated_applications(&AgeJsonDatabase::new(path, secret), apps),
+    }
+    println!("Database migrated successfully.");
+    println!(
+        "The previous database is kept at: {}",
+        backup_path.display()
+    );
+}
+
+/// Re-encrypts a password-protected database to the identity, after which the password,
+/// its recovery key and key file aren't used anymore
+fn migrate_to_identity() {
+    let Some(identity) = &config().identity else {
+        exit_with_error(
+            "Pass the identity file to encrypt the database to with --identity \
+            or set `identity` in the config",
+        );
+    };
+    let path = vault_path();
+    let format = match VaultFormat::detect_file(&path) {
+        Ok(Some(VaultFormat::AgeRecipients)) => {
+            println!(
+                "The database is already encrypted with {}",
+                VaultFormat::AgeRecipients
+            );
+            return;
+        }
+        Ok(Some(format)) => format,
+        Ok(None) => exit_with_error("There's no database to migrate yet"),
+        Err(err) => exit_with_error(err),
+    };
+    let keyfile = key_file();
+    check_key_file(keyfile.as_ref(), &path);
+    let secret = combine_key_file(keyfile.as_ref(), &get_secret());
+    let apps = open_database(path.clone(), secret)
+        .and_then(|db| db.get_applications())
+        .unwrap_or_else(|err| exit_with_error(err));
+    let backup_path = sidecar_path(&path, "passphrase.bak");
+    if let Err(err) = std::fs::copy(&path, &backup_path) {
+        exit_with_error(format!("Couldn't back up the database: {}", err));
+    }
+    let db = AgeIdentityDatabase::new(path.clone(), identity.clone());
+    if format == VaultFormat::Sqlite {
+        // The JSON file replaces the SQLite one rather than being written into it
+        if let Err(err) = std::fs::remove_file(&path) {
+            exit_with_error(format!("Couldn't replace the database: {}", err));
+        }
+    }
+    save_migrated_applications(&db, &apps);
+    if let Err(err) = Recovery::for_vault(&path)
+        .remove()
+        .and_then(|_| KeyFile::remove_fingerprint(&path))
+    {
+        exit_with_error(format!(
+            "Couldn't remove the files of the password: {}",
+            err
+        ));
+    }
+    println!("Database migrated successfully to age public key encryption.");
+    println!(
+        "The password-protected database is kept at: {}",
+        backup_path.display()
+    );
+}
+
+fn manage_profiles(command: &ProfileCommands) {
+    match command {
+        ProfileCommands::List {} => list_profiles(),
+        ProfileCommands::Create { name } => create_profile(name),
+        ProfileCommands::Remove { name } => remove_profile(name),
+    }
+}
+
+fn list_profiles() {
+    // The config in use has the settings of the current profile in place of the default ones
+    let config = load_config();
+    let mut names: Vec<String> = config.profiles.keys().cloned().collect();
+    if let Ok(entries) = std::fs::read_dir(data_dir().join("profiles")) {
+        names.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok()),
+        );
+    }
+    names.retain(|name| name != DEFAULT_PROFILE);
+    names.sort();
+    names.dedup();
+    names.insert(0, String::from(DEFAULT_PROFILE));
+    for name in names {
+        let current = if name == profile_name() { "*" } else { " " };
+        println!(
+            "{} {:<16} {}",
+            current,
+            name,
+            profile_vault_path(&config, &name).display()
+        );
+    }
+}
+
+fn create_profile(name: &str) {
+    if name == DEFAULT_PROFILE {
+        exit_with_error("The default profile always exists");
+    }
+    if vault_path().exists() {
+        exit_with_error(format!("Profile '{}' already exists", name));
+    }
+    let app = app();
+    save_all_apps(&app);
+    println!(
+        "Profile '{}' created, use it with `--profile {}`",
+        name, name
+    );
+}
+
+fn remove_profile(name: &str) {
+    if name == DEFAULT_PROFILE {
+        exit_with_error("The default profile can't be removed");
+    }
+    if !is_valid_profile_name(name) {
+        exit_with_error(format!("Invalid profile name '{}'", name));
+    }
+    let dir = profile_dir(name);
+    let config = load_config();
+    let config_path = config_dir().join("config.json");
+    let configured = config.profiles.contains_key(name) || config.profile.as_deref() == Some(name);
+    let vault = profile_vault_path(&config, name);
+    if !vault.starts_with(&dir) {
+        exit_with_error(format!(
+            "Profile '{}' keeps its database at {} as set in {}, \
+            remove the database and the profile from there instead",
+            name,
+            vault.display(),
+            config_path.display()
+        ));
+    }
+    if !dir.exists() {
+        if configured {
+            exit_with_error(format!(
+                "Profile '{}' is only set in {}, remove it from there",
+                name,
+                config_path.display()
+            ));
+        }
+        exit_with_error(format!("Profile '{}' doesn't exist", name));
+    }
+    let mut question = format!(
+        "Delete profile '{}' with all its generators and backups in {}?",
+        name,
+        dir.display()
+    );
+    if let Some(backups_dir) = &config.backups.dir {
+        question.push_str(&format!(
+            " Its backups in {} are kept.",
+            backups_dir.join(name).display()
+        ));
+    }
+    if configured {
+        question.push_str(&format!(
+            " Its settings in {} are kept.",
+            config_path.display()
+        ));
+    }
+    let mut p = Confirm::new(question).prompt().unwrap();
+    if !matches!(p.run().unwrap().as_str(), "y" | "Y" | "yes") {
+        println!("Aborting…");
+        return;
+    }
+    if let Err(err) = std::fs::remove_dir_all(&dir) {
+        exit_with_error(format!("Couldn't remove {}: {}", dir.display(), err));
+    }
+    println!("Profile '{}' removed", name);
+    if configured {
+        println!(
+            "It's still set in {}, remove it from there",
+            config_path.display()
+        );
+    }
+}
+
+fn list_backups() {
+    let backups = backups()
+        .list()
+        .unwrap_or_else(|err| exit_with_error(format!("Couldn't list backups: {}", err)));
+    if backups.is_empty() {
+        println!("There are no backups");
+        return;
+    }
+    for (i, backup) in backups.iter().enumerate() {
+        println!(
+            "{:>3}  {:<20} {}",
+            i + 1,
+            format_age(backup.created),
+            backup.path.display()
+        );
+    }
+}
+
+fn restore_backup(number: usize) {
+    let backups = backups();
+    let list = backups
+        .list()
+        .unwrap_or_else(|err| exit_with_error(format!("Couldn't list backups: {}", err)));
+    let backup = match number.checked_sub(1).and_then(|i| list.get(i)) {
+        Some(backup) => backup,
+        None => exit_with_error(format!("There is no backup #{}", number)),
+    };
+    if config().identity.is_some() {
+        let db = open_vault(backup.path.clone());
+        if let Err(err) = backups.restore(backup, db.as_ref()) {
+            exit_with_error(format!("Couldn't restore backup #{}: {}", number, err));
+        }
+    } else {
+        restore_password_backup(&backups, backup).unwrap_or_else(|err| {
+            exit_with_error(format!("Couldn't restore backup #{}: {}", number, err))
+        });
+    }
+    println!(
+        "Database restored from backup #{} ({})",
+        number,
+        format_age(backup.created)
+    );
+}
+
+/// Restores a password-protected backup along with the files that go with its password:
+/// the key file fingerprint is kept only if the backup needs the key file,
+/// and the recovery key is updated to the password the backup was opened with.
+fn restore_password_backup(backups: &Backups, backup: &Backup) -> Result<(), DatabaseError> {
+    let password = get_secret();
+    let keyfile = key_file();
+    // Backups taken before the key file protected the database open with the password alone
+    let candidates = match &keyfile {
+        Some(keyfile) => vec![Some(keyfile), None],
+        None => vec![None],
+    };
+    let mut opened = None;
+    for candidate in candidates {
+        let secret = combine_key_file(candidate, &password);
+        let db = open_database(backup.path.clone(), secret.clone())?;
+        match db.get_applications() {
+            Ok(_) => {
+                opened = Some((db, candidate, secret));
+                break;
+            }
+            Err(DatabaseError::WrongPassword | DatabaseError::WrongPasswordOrUnknownFile) => {
+                continue
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let Some((db, used_keyfile, secret)) = opened else {
+        if keyfile.is_none() && KeyFile::is_required(&vault_path()) {
+            println!("The backup may need the key file, pass it with --keyfile");
+        }
+        return Err(DatabaseError::WrongPassword);
+    };
+    backups.restore(backup, db.as_ref())?;
+    let vault = vault_path();
+    match used_keyfile {
+        Some(keyfile) => keyfile.save_fingerprint(&vault)?,
+        None => KeyFile::remove_fingerprint(&vault)?,
+    }
+    let recovery = Recovery::for_vault(&vault);
+    if recovery.exists() {
+        recovery.update(&secret)?;
+    }
+    if used_keyfile.is_none() && keyfile.is_some() {
+        println!(
+            "The backup isn't protected with the key file, \
+            it will be again the next time it's opened with one"
+        );
+    }
+    Ok(())
+}
+
+fn format_age(time: SystemTime) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or_default()
+        .as_secs();
+    let (value, unit) = match seconds {
+        0..=59 => (seconds, "second"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{} {}{} ago", value, unit, plural)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved_db(args: &[&str], config: serde_json::Value) -> PathBuf {
+        let cli = Cli::try_parse_from([&["totp"], args].concat()).unwrap();
+        let config = serde_json::from_value(config).unwrap();
+        resolve_config(&cli, config).db.unwrap()
+    }
+
+    #[test]
+    fn resolves_the_database_path() {
+        let config = serde_json::json!({
+            "db": "/config/totp.safe",
+            "profiles": {"work": {"db": "/work/totp.safe"}},
+        });
+        std::env::remove_var("RUSTOTPONY_DB");
+        std::env::remove_var("RUSTOTPONY_PROFILE");
+        assert_eq!(
+            resolved_db(&["list"], config.clone()),
+            Path::new("/config/totp.safe")
+        );
+        assert_eq!(
+            resolved_db(&["--db", "/cli/totp.safe", "list"], config.clone()),
+            Path::new("/cli/totp.safe")
+        );
+        assert_eq!(
+            resolved_db(&["--profile", "work", "list"], config.clone()),
+            Path::new("/work/totp.safe")
+        );
+        assert_eq!(
+            resolved_db(
+                &["--profile", "work", "--db", "/cli/totp.safe", "list"],
+                config.clone()
+            ),
+            Path::new("/cli/totp.safe")
+        );
+        let mut with_profile = config.clone();
+        with_profile["profile"] = serde_json::json!("work");
+        assert_eq!(
+            resolved_db(&["list"], with_profile),
+            Path::new("/work/totp.safe")
+        );
+
+        std::env::set_var("RUSTOTPONY_DB", "/env/totp.safe");
+        let from_env = resolved_db(&["list"], config.clone());
+        let from_cli = resolved_db(&["--db", "/cli/totp.safe", "list"], config);
+        std::env::remove_var("RUSTOTPONY_DB");
+        assert_eq!(from_env, Path::new("/env/totp.safe"));
+        assert_eq!(from_cli, Path::new("/cli/totp.safe"));
+    }
+
+    #[test]
+    fn places_profiles_in_the_data_directory() {
+        let config = Config::default();
+        assert_eq!(
+            profile_vault_path(&config, DEFAULT_PROFILE),
+            data_dir().join("totp.safe")
+        );
+        assert_eq!(
+            profile_vault_path(&config, "work"),
+            data_dir().join("profiles").join("work").join("totp.safe")
+        );
+    }
+
+    #[test]
+    fn moves_the_legacy_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_dir = dir.path().join(".rustotpony");
+        let data_dir = dir.path().join("data").join("rustotpony");
+        std::fs::create_dir(&legacy_dir).unwrap();
+        std::fs::write(legacy_dir.join("totp.safe"), b"vault").unwrap();
+        std::fs::write(legacy_dir.join("notes.txt"), b"mine").unwrap();
+
+        move_legacy_files(&legacy_dir, &data_dir);
+        assert_eq!(std::fs::read(data_dir.join("totp.safe")).unwrap(), b"vault");
+        assert!(!legacy_dir.join("totp.safe").exists());
+        assert_eq!(
+            std::fs::read(legacy_dir.join("notes.txt")).unwrap(),
+            b"mine"
+        );
+        assert!(!data_dir.join("notes.txt").exists());
+
+        // A database reappearing in the legacy directory doesn't replace the moved one
+        std::fs::write(legacy_dir.join("totp.safe"), b"old").unwrap();
+        move_legacy_files(&legacy_dir, &data_dir);
+        assert_eq!(std::fs::read(legacy_dir.join("totp.safe")).unwrap(), b"old");
+        assert_eq!(std::fs::read(data_dir.join("totp.safe")).unwrap(), b"vault");
+
+        std::fs::remove_file(legacy_dir.join("notes.txt")).unwrap();
+        std::fs::remove_file(data_dir.join("totp.safe")).unwrap();
+        move_legacy_files(&legacy_dir, &data_dir);
+        assert!(!legacy_dir.exists());
+    }
 }
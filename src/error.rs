@@ -0,0 +1,144 @@
+use super::VaultFormat;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors of managing applications of [`RusTOTPony`](super::RusTOTPony)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApplicationError {
+    /// There is no application with the given name
+    NotFound(String),
+    /// An application with the given name already exists
+    AlreadyExists(String),
+    /// The secret isn't valid base32
+    InvalidSecret,
+    /// A glob pattern couldn't be parsed
+    InvalidPattern(String, String),
+}
+
+impl fmt::Display for ApplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApplicationError::NotFound(name) => write!(f, "Application '{}' wasn't found", name),
+            ApplicationError::AlreadyExists(name) => {
+                write!(f, "Application with name '{}' already exists!", name)
+            }
+            ApplicationError::InvalidSecret => f.write_str("Couldn't decode secret key"),
+            ApplicationError::InvalidPattern(pattern, err) => {
+                write!(f, "Invalid pattern '{}': {}", pattern, err)
+            }
+        }
+    }
+}
+
+impl Error for ApplicationError {}
+
+/// Errors of reading and writing a database
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DatabaseError {
+    /// The file couldn't be read or written
+    Io(io::Error),
+    /// The password doesn't match the one the file was encrypted with
+    WrongPassword,
+    /// A file without header couldn't be decrypted: either the password is wrong,
+    /// or the file isn't a RusTOTPony database, which can't be told apart for that format
+    WrongPasswordOrUnknownFile,
+    /// The file was damaged or tampered with
+    Corrupt,
+    /// The file was written in a format version this one doesn't know
+    UnsupportedVersion,
+    /// The file isn't a RusTOTPony database at all
+    UnknownFormat(PathBuf),
+    /// The file is in a different format than the implementation reading it
+    FormatMismatch {
+        expected: VaultFormat,
+        found: VaultFormat,
+    },
+    /// The file is in a format this build doesn't support, see the crate features
+    UnsupportedFormat(VaultFormat),
+    /// The content couldn't be encrypted
+    Encryption(String),
+    /// Another process changed the file since it was loaded
+    ConcurrentModification,
+    /// The identity file couldn't be read or used
+    Identity(String),
+    /// None of the identities can decrypt the file
+    WrongIdentity,
+    /// A public key to encrypt the file to couldn't be parsed
+    InvalidRecipient(String),
+    /// The recovery key doesn't match the one the password was encrypted with
+    WrongRecoveryKey,
+    /// The key file couldn't be read
+    KeyFile(String),
+    /// The key file isn't the one the database was encrypted with
+    WrongKeyFile,
+    /// The database isn't protected by a password that could be changed
+    NoPassword,
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Io(err) => write!(f, "{}", err),
+            DatabaseError::WrongPassword => f.write_str("Wrong password"),
+            DatabaseError::WrongPasswordOrUnknownFile => {
+                f.write_str("Wrong password, or the file is not a RusTOTPony database")
+            }
+            DatabaseError::Corrupt => f.write_str("The database file is corrupted"),
+            DatabaseError::UnsupportedVersion => f.write_str(
+                "The database was written by a newer version of RusTOTPony, please upgrade",
+            ),
+            DatabaseError::UnknownFormat(path) => write!(
+                f,
+                "{} is not a RusTOTPony database or its format is unknown",
+                path.display()
+            ),
+            DatabaseError::FormatMismatch { expected, found } => write!(
+                f,
+                "The database is encrypted with {} and can't be opened with {}",
+                found, expected
+            ),
+            DatabaseError::UnsupportedFormat(format) => write!(
+                f,
+                "This build of RusTOTPony can't open databases encrypted with {}",
+                format
+            ),
+            DatabaseError::Encryption(err) => write!(f, "Couldn't encrypt the database: {}", err),
+            DatabaseError::ConcurrentModification => f.write_str(
+                "The database was changed by another process since it was loaded, please try again",
+            ),
+            DatabaseError::Identity(err) => write!(f, "Couldn't use the identity: {}", err),
+            DatabaseError::WrongIdentity => {
+                f.write_str("The database isn't encrypted to the given identity")
+            }
+            DatabaseError::InvalidRecipient(recipient) => {
+                write!(f, "Invalid public key '{}'", recipient)
+            }
+            DatabaseError::WrongRecoveryKey => f.write_str("Wrong recovery key"),
+            DatabaseError::KeyFile(err) => write!(f, "Couldn't use the key file: {}", err),
+            DatabaseError::WrongKeyFile => {
+                f.write_str("The key file isn't the one the database was encrypted with")
+            }
+            DatabaseError::NoPassword => f.write_str("The database isn't protected by a password"),
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DatabaseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(err: io::Error) -> Self {
+        DatabaseError::Io(err)
+    }
+}
@@ -0,0 +1,433 @@
+use super::migration::parse_migration_uri;
+use super::{GenApp, ImportedEntry};
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Backup formats recognised by [`BackupFormat::detect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupFormat {
+    /// JSON printed by `totp export`
+    RusTOTPony,
+    /// Plain text with one `otpauth://` URI per line, as written by Ente Auth, Proton Pass and others
+    OtpauthUris,
+    /// `otpauth-migration://` URIs exported by Google Authenticator
+    OtpauthMigration,
+    /// Unencrypted Aegis JSON backup
+    Aegis,
+    /// Unencrypted andOTP JSON backup
+    AndOtp,
+    /// Unencrypted 2FAS JSON backup
+    TwoFas,
+}
+
+impl fmt::Display for BackupFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BackupFormat::RusTOTPony => "RusTOTPony JSON",
+            BackupFormat::OtpauthUris => "otpauth URI list",
+            BackupFormat::OtpauthMigration => "Google Authenticator migration",
+            BackupFormat::Aegis => "Aegis backup",
+            BackupFormat::AndOtp => "andOTP backup",
+            BackupFormat::TwoFas => "2FAS backup",
+        };
+        f.write_str(name)
+    }
+}
+
+impl BackupFormat {
+    /// Guesses the format of a backup from its content
+    pub fn detect(content: &str) -> Option<Self> {
+        let content = content.trim_start();
+        if content.starts_with('{') || content.starts_with('[') {
+            return match serde_json::from_str::<Value>(content).ok()? {
+                Value::Object(o) if o.contains_key("db") && o.contains_key("header") => {
+                    Some(BackupFormat::Aegis)
+                }
+                Value::Object(o)
+                    if o.contains_key("services") && o.contains_key("schemaVersion") =>
+                {
+                    Some(BackupFormat::TwoFas)
+                }
+                Value::Object(o) if o.values().all(|v| v.get("secret_bytes").is_some()) => {
+                    Some(BackupFormat::RusTOTPony)
+                }
+                Value::Array(a) if a.iter().all(|v| v.get("secret").is_some()) => {
+                    Some(BackupFormat::AndOtp)
+                }
+                _ => None,
+            };
+        }
+        let first_line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?
+            .to_ascii_lowercase();
+        if first_line.starts_with("otpauth-migration://") {
+            Some(BackupFormat::OtpauthMigration)
+        } else if first_line.starts_with("otpauth://") {
+            Some(BackupFormat::OtpauthUris)
+        } else {
+            None
+        }
+    }
+
+    /// Parses a backup of this format into one entry per generator
+    pub fn parse(self, content: &str) -> Result<Vec<ImportedEntry>, String> {
+        match self {
+            BackupFormat::RusTOTPony => parse_rustotpony(content),
+            BackupFormat::OtpauthUris => Ok(parse_otpauth_uris(content)),
+            BackupFormat::OtpauthMigration => Ok(parse_otpauth_migration(content)),
+            BackupFormat::Aegis => parse_aegis(content),
+            BackupFormat::AndOtp => parse_andotp(content),
+            BackupFormat::TwoFas => parse_twofas(content),
+        }
+    }
+}
+
+/// Detects the format of a backup and parses it
+pub fn parse_backup(content: &str) -> Result<(BackupFormat, Vec<ImportedEntry>), String> {
+    let format = BackupFormat::detect(content).ok_or("Couldn't recognise the backup format")?;
+    Ok((format, format.parse(content)?))
+}
+
+fn parse_rustotpony(content: &str) -> Result<Vec<ImportedEntry>, String> {
+    let apps: HashMap<String, GenApp> =
+        serde_json::from_str(content).map_err(|e| format!("Invalid RusTOTPony JSON: {}", e))?;
+    let mut names: Vec<&String> = apps.keys().collect();
+    names.sort();
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let app = &apps[name];
+            let entry = GenApp::from_secret(&app.name, &app.username, &app.secret)
+                .ok_or_else(|| String::from("Couldn't decode secret key"));
+            (name.clone(), entry)
+        })
+        .collect())
+}
+
+/// Blank lines and `#` comments are skipped, invalid entries are named after their line number
+fn parse_otpauth_uris(content: &str) -> Vec<ImportedEntry> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match GenApp::from_otpauth_uri(line) {
+            Ok(app) => (app.name.clone(), Ok(app)),
+            Err(err) => (format!("line {}", number), Err(err)),
+        })
+        .collect()
+}
+
+/// Blank lines and `#` comments are skipped, lines that can't be decoded are named after their number
+fn parse_otpauth_migration(content: &str) -> Vec<ImportedEntry> {
+    let mut entries = vec![];
+    let lines = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    for (number, line) in lines {
+        match parse_migration_uri(line) {
+            Ok(apps) => {
+                for app in apps {
+                    entries.push(with_name(entries.len(), app));
+                }
+            }
+            Err(err) => entries.push((format!("line {}", number), Err(err))),
+        }
+    }
+    entries
+}
+
+fn parse_aegis(content: &str) -> Result<Vec<ImportedEntry>, String> {
+    let backup: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let entries = match &backup["db"] {
+        Value::String(_) => return Err(String::from("Encrypted Aegis backups are not supported")),
+        db => db["entries"].as_array().ok_or("Missing Aegis entries")?,
+    };
+    Ok(entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let info = &entry["info"];
+            let app = check_parameters(
+                str_field(entry, "type"),
+                str_field(info, "algo"),
+                &info["digits"],
+                &info["period"],
+            )
+            .and_then(|_| {
+                build_application(
+                    str_field(entry, "issuer"),
+                    str_field(entry, "name"),
+                    str_field(info, "secret"),
+                )
+            });
+            with_name(i, app)
+        })
+        .collect())
+}
+
+fn parse_andotp(content: &str) -> Result<Vec<ImportedEntry>, String> {
+    let entries: Vec<Value> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    Ok(entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let app = check_parameters(
+                str_field(entry, "type"),
+                str_field(entry, "algorithm"),
+                &entry["digits"],
+                &entry["period"],
+            )
+            .and_then(|_| {
+                build_application(
+                    str_field(entry, "issuer"),
+                    str_field(entry, "label"),
+                    str_field(entry, "secret"),
+                )
+            });
+            with_name(i, app)
+        })
+        .collect())
+}
+
+fn parse_twofas(content: &str) -> Result<Vec<ImportedEntry>, String> {
+    let backup: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    if backup.get("servicesEncrypted").is_some() {
+        return Err(String::from("Encrypted 2FAS backups are not supported"));
+    }
+    let services = backup["services"]
+        .as_array()
+        .ok_or("Missing 2FAS services")?;
+    Ok(services
+        .iter()
+        .enumerate()
+        .map(|(i, service)| {
+            let otp = &service["otp"];
+            let issuer = match str_field(otp, "issuer") {
+                "" => str_field(service, "name"),
+                issuer => issuer,
+            };
+            let app = check_parameters(
+                str_field(otp, "tokenType"),
+                str_field(otp, "algorithm"),
+                &otp["digits"],
+                &otp["period"],
+            )
+            .and_then(|_| {
+                build_application(
+                    issuer,
+                    str_field(otp, "account"),
+                    str_field(service, "secret"),
+                )
+            });
+            with_name(i, app)
+        })
+        .collect())
+}
+
+fn str_field<'a>(value: &'a Value, field: &str) -> &'a str {
+    value[field].as_str().unwrap_or("").trim()
+}
+
+/// Rejects generators RusTOTPony can't produce codes for. Missing values mean defaults.
+fn check_parameters(
+    kind: &str,
+    algorithm: &str,
+    digits: &Value,
+    period: &Value,
+) -> Result<(), String> {
+    if !kind.is_empty() && !kind.eq_ignore_ascii_case("totp") {
+        return Err(format!("Unsupported OTP type '{}'", kind));
+    }
+    if !algorithm.is_empty() && !algorithm.eq_ignore_ascii_case("SHA1") {
+        return Err(format!("Unsupported algorithm '{}'", algorithm));
+    }
+    if !digits.is_null() && digits.as_u64() != Some(6) {
+        return Err(format!("Unsupported number of digits '{}'", digits));
+    }
+    if !period.is_null() && period.as_u64() != Some(30) {
+        return Err(format!("Unsupported period '{}'", period));
+    }
+    Ok(())
+}
+
+fn build_application(issuer: &str, account: &str, secret: &str) -> Result<GenApp, String> {
+    let (name, username) = if issuer.is_empty() {
+        (account, "")
+    } else {
+        (issuer, account)
+    };
+    if name.is_empty() {
+        return Err(String::from("Missing name"));
+    }
+    GenApp::from_secret(name, username, secret)
+        .ok_or_else(|| String::from("Couldn't decode secret key"))
+}
+
+/// Names an entry after its generator, or after its position if it couldn't be parsed
+fn with_name(index: usize, app: Result<GenApp, String>) -> ImportedEntry {
+    let name = match &app {
+        Ok(app) => app.name.clone(),
+        Err(_) => format!("entry #{}", index + 1),
+    };
+    (name, app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SECRET;
+
+    use base64::Engine;
+
+    fn names(entries: &[ImportedEntry]) -> Vec<(&str, Result<&str, &str>)> {
+        entries
+            .iter()
+            .map(|(name, app)| {
+                let username = app
+                    .as_ref()
+                    .map(|app| app.get_username())
+                    .map_err(String::as_str);
+                (name.as_str(), username)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_formats() {
+        let cases = [
+            (
+                r#"{"github": {"name": "github", "secret": "X", "username": "", "secret_bytes": []}}"#,
+                BackupFormat::RusTOTPony,
+            ),
+            (
+                "# exported\n\notpauth://totp/x?secret=X",
+                BackupFormat::OtpauthUris,
+            ),
+            (
+                "OTPAUTH-MIGRATION://offline?data=",
+                BackupFormat::OtpauthMigration,
+            ),
+            (
+                r#"{"version": 1, "header": {}, "db": {}}"#,
+                BackupFormat::Aegis,
+            ),
+            (r#"[{"secret": "X"}]"#, BackupFormat::AndOtp),
+            (
+                r#"{"services": [], "schemaVersion": 4}"#,
+                BackupFormat::TwoFas,
+            ),
+        ];
+        for (content, format) in cases {
+            assert_eq!(BackupFormat::detect(content), Some(format), "{}", content);
+        }
+        for content in ["", "hello", "{", r#"{"a": 1}"#, "[1]"] {
+            assert_eq!(BackupFormat::detect(content), None, "{}", content);
+        }
+    }
+
+    #[test]
+    fn parses_aegis_backups() {
+        let backup = format!(
+            r#"{{"header": {{}}, "db": {{"entries": [
+                {{"type": "totp", "name": "john", "issuer": "ACME", "info": {{"secret": "{0}", "algo": "SHA1", "digits": 6, "period": 30}}}},
+                {{"type": "hotp", "name": "counter", "issuer": "", "info": {{"secret": "{0}"}}}},
+                {{"type": "totp", "name": "long", "issuer": "", "info": {{"secret": "{0}", "digits": 8}}}}
+            ]}}}}"#,
+            SECRET
+        );
+        let (format, entries) = parse_backup(&backup).unwrap();
+        assert_eq!(format, BackupFormat::Aegis);
+        assert_eq!(
+            names(&entries),
+            [
+                ("ACME", Ok("john")),
+                ("entry #2", Err("Unsupported OTP type 'hotp'")),
+                ("entry #3", Err("Unsupported number of digits '8'")),
+            ]
+        );
+        assert_eq!(
+            parse_backup(r#"{"header": {}, "db": "encrypted"}"#).unwrap_err(),
+            "Encrypted Aegis backups are not supported"
+        );
+    }
+
+    #[test]
+    fn parses_andotp_and_twofas_backups() {
+        let andotp = format!(
+            r#"[{{"secret": "{0}", "issuer": "", "label": "GitHub", "type": "TOTP"}},
+                {{"secret": "{0}", "issuer": "", "label": "", "type": "TOTP"}}]"#,
+            SECRET
+        );
+        let (_, entries) = parse_backup(&andotp).unwrap();
+        assert_eq!(
+            names(&entries),
+            [("GitHub", Ok("")), ("entry #2", Err("Missing name"))]
+        );
+
+        let twofas = format!(
+            r#"{{"schemaVersion": 4, "services": [
+                {{"name": "Bank", "secret": "{0}", "otp": {{"account": "bob", "tokenType": "TOTP"}}}},
+                {{"name": "Shop", "secret": "{0}", "otp": {{"issuer": "Store", "period": 60}}}}
+            ]}}"#,
+            SECRET
+        );
+        let (_, entries) = parse_backup(&twofas).unwrap();
+        assert_eq!(
+            names(&entries),
+            [
+                ("Bank", Ok("bob")),
+                ("entry #2", Err("Unsupported period '60'"))
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_comments_of_migration_uris() {
+        let parameters = [
+            &[0x0a, 9][..],
+            b"123321555",
+            &[0x12, 6],
+            b"GitHub",
+            &[0x30, 2],
+        ]
+        .concat();
+        let payload = [&[0x0a, parameters.len() as u8][..], &parameters].concat();
+        let data = base64::engine::general_purpose::STANDARD.encode(payload);
+        let content = format!(
+            "# exported from phone\notpauth-migration://offline?data={0}\n\
+            otpauth-migration://offline?data\notpauth-migration://offline?data={0}\n",
+            data.replace('=', "%3D")
+        );
+        let (format, entries) = parse_backup(&content).unwrap();
+        assert_eq!(format, BackupFormat::OtpauthMigration);
+        assert_eq!(
+            names(&entries),
+            [
+                ("GitHub", Ok("")),
+                ("line 3", Err("Missing migration data")),
+                ("GitHub", Ok("")),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_invalid_uris_after_their_line() {
+        let content = format!(
+            "otpauth://totp/GitHub?secret={0}\n\n# comment\notpauth://totp/x\n",
+            SECRET
+        );
+        let (_, entries) = parse_backup(&content).unwrap();
+        assert_eq!(
+            names(&entries),
+            [("GitHub", Ok("")), ("line 4", Err("Missing secret"))]
+        );
+    }
+}
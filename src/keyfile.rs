@@ -0,0 +1,189 @@
+use super::atomic;
+use super::{open_database, sidecar_path, DatabaseError};
+
+use sha2::{Digest, Sha256};
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const FINGERPRINT_CONTEXT: &[u8] = b"RusTOTPony key file check";
+
+/// Key file required along with the password to unlock a database.
+///
+/// The passphrase of such a database combines the password with a hash of the key file.
+/// A fingerprint of the key file is kept next to the database in a `.keyfile` file,
+/// so that a missing or wrong key file can be told apart from a wrong password.
+pub struct KeyFile {
+    hash: Vec<u8>,
+}
+
+impl KeyFile {
+    pub fn read(path: &Path) -> Result<Self, DatabaseError> {
+        let content = std::fs::read(path).map_err(|err| {
+            DatabaseError::KeyFile(format!("Couldn't read {}: {}", path.display(), err))
+        })?;
+        if content.is_empty() {
+            return Err(DatabaseError::KeyFile(format!(
+                "{} is empty",
+                path.display()
+            )));
+        }
+        Ok(KeyFile {
+            hash: Sha256::digest(&content).to_vec(),
+        })
+    }
+
+    /// Returns the passphrase of the database for the given password
+    pub fn combine(&self, password: &str) -> String {
+        format!("{}:{}", password, to_hex(&self.hash))
+    }
+
+    /// Tells whether the database file needs a key file to be unlocked
+    pub fn is_required(vault: &Path) -> bool {
+        vault.exists() && fingerprint_path(vault).exists()
+    }
+
+    /// Tells whether the database opens with the password combined with this key file,
+    /// e.g. to find out that it's protected already when its fingerprint was lost
+    pub fn unlocks(&self, vault: &Path, password: &str) -> Result<bool, DatabaseError> {
+        match open_database(vault.to_path_buf(), self.combine(password))?.get_applications() {
+            Ok(_) => Ok(true),
+            Err(DatabaseError::WrongPassword | DatabaseError::WrongPasswordOrUnknownFile) => {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Checks that this is the key file the database was encrypted with
+    pub fn check(&self, vault: &Path) -> Result<(), DatabaseError> {
+        match std::fs::read_to_string(fingerprint_path(vault)) {
+            Ok(fingerprint) if fingerprint.trim() == self.fingerprint() => Ok(()),
+            Ok(_) => Err(DatabaseError::WrongKeyFile),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Marks the database as encrypted with this key file
+    pub fn save_fingerprint(&self, vault: &Path) -> Result<(), DatabaseError> {
+        let content = format!("{}\n", self.fingerprint());
+        atomic::write_file(&fingerprint_path(vault), content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Marks the database as not needing a key file anymore
+    pub fn remove_fingerprint(vault: &Path) -> Result<(), DatabaseError> {
+        match std::fs::remove_file(fingerprint_path(vault)) {
+            Ok(_) => Ok(()),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(FINGERPRINT_CONTEXT);
+        hasher.update(&self.hash);
+        to_hex(&hasher.finalize())
+    }
+}
+
+fn fingerprint_path(vault: &Path) -> PathBuf {
+    sidecar_path(vault, "keyfile")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, JsonDatabase, JsonDatabaseTrait};
+
+    use std::collections::HashMap;
+
+    use tempfile::TempDir;
+
+    fn key_file(dir: &TempDir, name: &str, content: &str) -> KeyFile {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        KeyFile::read(&path).unwrap()
+    }
+
+    #[test]
+    fn checks_the_key_file_of_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("totp.safe");
+        let right = key_file(&dir, "right.key", "right");
+        let wrong = key_file(&dir, "wrong.key", "wrong");
+
+        std::fs::write(&vault, "database").unwrap();
+        assert!(!KeyFile::is_required(&vault));
+        right.check(&vault).unwrap();
+        wrong.check(&vault).unwrap();
+
+        right.save_fingerprint(&vault).unwrap();
+        assert!(KeyFile::is_required(&vault));
+        right.check(&vault).unwrap();
+        assert!(matches!(
+            wrong.check(&vault),
+            Err(DatabaseError::WrongKeyFile)
+        ));
+
+        KeyFile::remove_fingerprint(&vault).unwrap();
+        assert!(!KeyFile::is_required(&vault));
+        KeyFile::remove_fingerprint(&vault).unwrap();
+    }
+
+    #[test]
+    fn recognises_protected_databases_without_the_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("totp.safe");
+        let right = key_file(&dir, "right.key", "right");
+        let wrong = key_file(&dir, "wrong.key", "wrong");
+        JsonDatabase::new(vault.clone(), right.combine("password"))
+            .save_applications(&HashMap::new())
+            .unwrap();
+        right.save_fingerprint(&vault).unwrap();
+
+        std::fs::remove_file(fingerprint_path(&vault)).unwrap();
+        assert!(!KeyFile::is_required(&vault));
+        assert!(right.unlocks(&vault, "password").unwrap());
+        assert!(!right.unlocks(&vault, "wrong").unwrap());
+        assert!(!wrong.unlocks(&vault, "password").unwrap());
+
+        right.save_fingerprint(&vault).unwrap();
+        assert!(KeyFile::is_required(&vault));
+        right.check(&vault).unwrap();
+    }
+
+    #[test]
+    fn combines_the_password_with_the_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let right = key_file(&dir, "right.key", "right");
+        let same = key_file(&dir, "copy.key", "right");
+        let wrong = key_file(&dir, "wrong.key", "wrong");
+        assert_eq!(right.combine("password"), same.combine("password"));
+        assert_ne!(right.combine("password"), wrong.combine("password"));
+        assert_ne!(right.combine("password"), right.combine("other"));
+        assert!(right.combine("password").starts_with("password:"));
+    }
+
+    #[test]
+    fn refuses_missing_or_empty_key_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.key");
+        assert!(matches!(
+            KeyFile::read(&missing),
+            Err(DatabaseError::KeyFile(_))
+        ));
+        let empty = dir.path().join("empty.key");
+        std::fs::write(&empty, "").unwrap();
+        assert!(matches!(
+            KeyFile::read(&empty),
+            Err(DatabaseError::KeyFile(_))
+        ));
+    }
+}
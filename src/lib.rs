@@ -2,15 +2,59 @@ extern crate age;
 extern crate base32;
 extern crate crypto;
 extern crate dirs;
+extern crate glob;
 extern crate rand;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+extern crate scrypt;
 extern crate serde_json;
 extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
 extern crate totp_lite;
 
 #[macro_use]
 extern crate serde_derive;
 
+mod atomic;
+mod backup;
+mod error;
+mod identity;
+mod import;
+mod keyfile;
+mod lock;
+mod memory;
+mod migration;
+mod otpauth;
+mod pam;
+mod pass;
+mod recovery;
+mod schema;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod vault;
+
+pub use backup::{Backup, Backups};
+pub use error::{ApplicationError, DatabaseError};
+pub use identity::{AgeIdentityDatabase, RecipientsFile};
+pub use import::{parse_backup, BackupFormat};
+pub use keyfile::KeyFile;
+pub use memory::MemoryDatabase;
+pub use pass::PasswordStore;
+pub use recovery::Recovery;
+pub use schema::JsonDatabaseSchema;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDatabase;
+pub use vault::{open_database, sidecar_path, VaultFormat};
+
+use lock::{read_tracked, write_tracked};
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes_gcm::AesGcm;
 use crypto::buffer::{BufferResult, ReadBuffer, WriteBuffer};
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::util::fixed_time_eq;
 use crypto::{aes, blockmodes, buffer, symmetriccipher};
 use sha2::{Digest, Sha256};
 
@@ -18,26 +62,53 @@ use totp_lite::{totp_custom, Sha1, DEFAULT_STEP};
 
 use rand::prelude::*;
 
-use std::collections::HashMap;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::ErrorKind;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::time::SystemTime;
 
-const DATABASE_VERSION: u8 = 1;
-
 pub struct RusTOTPony<DB: Database> {
     database: DB,
     applications: HashMap<String, GenApp>,
+    backups: Option<Backups>,
+    pending: RefCell<Pending>,
+}
+
+/// Changes not flushed to the database yet
+enum Pending {
+    /// Changes to save one by one, in the order they were made
+    Changes(VecDeque<Change>),
+    /// Every application has to be saved, e.g. to re-encrypt them
+    All,
+}
+
+/// A change to a single application, along with the application as it was after it
+enum Change {
+    Insert(GenApp),
+    /// The application saved under the name, which may have been renamed
+    Update(String, GenApp),
+    Remove(String),
 }
 
 impl<DB: Database> RusTOTPony<DB> {
-    pub fn new(db: DB) -> RusTOTPony<DB> {
-        RusTOTPony {
-            applications: db.get_applications(),
+    pub fn new(db: DB) -> Result<RusTOTPony<DB>, DatabaseError> {
+        Ok(RusTOTPony {
+            applications: db.get_applications()?,
             database: db,
-        }
+            backups: None,
+            pending: RefCell::new(Pending::Changes(VecDeque::new())),
+        })
+    }
+
+    /// Keeps rotating backups of the database file, taken on every flush
+    pub fn with_backups(mut self, backups: Backups) -> Self {
+        self.backups = Some(backups);
+        self
+    }
+
+    pub fn get_database(&self) -> &DB {
+        &self.database
     }
 
     pub fn create_application(
@@ -45,82 +116,214 @@ impl<DB: Database> RusTOTPony<DB> {
         name: &str,
         username: &str,
         secret: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), ApplicationError> {
         if let Some(secret_bytes) = GenApp::base32_to_bytes(secret) {
-            let new_app = GenApp::new(name, username, secret, secret_bytes);
-            if self.applications.contains_key(name) {
-                Err(format!("Application with name '{}' already exists!", name))
-            } else {
-                self.applications.insert(String::from(name), new_app);
-                Ok(())
-            }
+            self.add_application(GenApp::new(name, username, secret, secret_bytes))
         } else {
-            Err(String::from("Couldn't decode secret key"))
+            Err(ApplicationError::InvalidSecret)
         }
     }
 
-    pub fn delete_application(&mut self, name: &str) -> Result<(), String> {
-        if self.applications.remove(name).is_some() {
-            Ok(())
+    pub fn add_application(&mut self, app: GenApp) -> Result<(), ApplicationError> {
+        if self.applications.contains_key(&app.name) {
+            Err(ApplicationError::AlreadyExists(app.name))
         } else {
-            Err(format!(
-                "Application with the name '{}' doesn't exist",
-                name
-            ))
+            self.record(Change::Insert(app.clone()));
+            self.applications.insert(app.name.clone(), app);
+            Ok(())
         }
     }
 
-    pub fn rename_application(&mut self, name: &str, newname: &str) -> Result<(), String> {
-        if let Some(app) = self.applications.get_mut(name) {
-            app.name = String::from(newname);
+    pub fn delete_application(&mut self, name: &str) -> Result<(), ApplicationError> {
+        if self.applications.remove(name).is_some() {
+            self.record(Change::Remove(String::from(name)));
             Ok(())
         } else {
-            Err(format!("Application '{}' wasn't found", name))
+            Err(ApplicationError::NotFound(String::from(name)))
         }
     }
 
-    pub fn get_applications(&self) -> Result<&HashMap<String, GenApp>, String> {
-        if self.applications.is_empty() {
-            Err(String::from("There are no applications"))
-        } else {
-            Ok(&self.applications)
+    pub fn rename_application(
+        &mut self,
+        name: &str,
+        newname: &str,
+    ) -> Result<(), ApplicationError> {
+        if name != newname
+            && self.applications.contains_key(name)
+            && self.applications.contains_key(newname)
+        {
+            return Err(ApplicationError::AlreadyExists(String::from(newname)));
         }
+        let Some(mut app) = self.applications.remove(name) else {
+            return Err(ApplicationError::NotFound(String::from(name)));
+        };
+        app.name = String::from(newname);
+        self.record(Change::Update(String::from(name), app.clone()));
+        self.applications.insert(app.name.clone(), app);
+        Ok(())
     }
 
-    pub fn get_application(&self, name: &str) -> Result<&GenApp, String> {
-        if let Some(app) = self.applications.get(name) {
-            Ok(app)
-        } else {
-            Err(format!("Application '{}' wasn't found", name))
-        }
+    pub fn get_applications(&self) -> &HashMap<String, GenApp> {
+        &self.applications
+    }
+
+    /// Returns applications whose names match any of the given glob patterns.
+    /// An empty list of patterns matches every application.
+    pub fn get_applications_matching(
+        &self,
+        patterns: &[String],
+    ) -> Result<HashMap<&str, &GenApp>, ApplicationError> {
+        let patterns = patterns
+            .iter()
+            .map(|p| {
+                glob::Pattern::new(p)
+                    .map_err(|e| ApplicationError::InvalidPattern(p.clone(), e.msg.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self
+            .applications
+            .iter()
+            .filter(|(name, _)| patterns.is_empty() || patterns.iter().any(|p| p.matches(name)))
+            .map(|(name, app)| (name.as_str(), app))
+            .collect())
+    }
+
+    pub fn get_application(&self, name: &str) -> Result<&GenApp, ApplicationError> {
+        self.applications
+            .get(name)
+            .ok_or_else(|| ApplicationError::NotFound(String::from(name)))
     }
 
     pub fn delete_all_applications(&mut self) {
         self.applications = HashMap::new();
+        self.pending.replace(Pending::All);
     }
 
-    pub fn flush(&self) {
-        self.database.save_applications(&self.applications);
+    /// Saves the changes made since the last flush one by one, in the order they were made,
+    /// if the database [supports it](Database::supports_row_updates).
+    /// Every application is saved at once otherwise, or after they were all deleted or the password changed.
+    /// Changes that were saved aren't saved again by the next flush when a later one fails.
+    pub fn flush(&self) -> Result<(), DatabaseError> {
+        let mut pending = self.pending.borrow_mut();
+        if matches!(&*pending, Pending::Changes(changes) if changes.is_empty()) {
+            return Ok(());
+        }
+        if let Some(backups) = &self.backups {
+            backups.create()?;
+        }
+        let apps = &self.applications;
+        match &mut *pending {
+            Pending::Changes(changes) if self.database.supports_row_updates() => {
+                while let Some(change) = changes.front() {
+                    match change {
+                        Change::Insert(app) => self.database.insert_application(app, apps),
+                        Change::Update(name, app) => {
+                            self.database.update_application(name, app, apps)
+                        }
+                        Change::Remove(name) => self.database.remove_application(name, apps),
+                    }?;
+                    changes.pop_front();
+                }
+            }
+            _ => {
+                self.database.save_applications(apps)?;
+                *pending = Pending::Changes(VecDeque::new());
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves every application at once, even if none of them changed,
+    /// e.g. to create the database file or re-encrypt it for other recipients
+    pub fn flush_all(&self) -> Result<(), DatabaseError> {
+        self.pending.replace(Pending::All);
+        self.flush()
+    }
+
+    /// Re-encrypts the database with a new password
+    pub fn change_password(&mut self, secret: String) -> Result<(), DatabaseError> {
+        self.database.set_secret(secret)?;
+        self.flush_all()
+    }
+
+    fn record(&mut self, change: Change) {
+        if let Pending::Changes(changes) = self.pending.get_mut() {
+            changes.push_back(change);
+        }
     }
 }
 
 pub trait Database {
-    fn get_applications(&self) -> HashMap<String, GenApp>;
-    fn save_applications(&self, applications: &HashMap<String, GenApp>);
+    fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError>;
+    fn save_applications(
+        &self,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError>;
+    /// Sets the password used for the following saves.
+    /// Databases that aren't protected by a password refuse it.
+    fn set_secret(&mut self, _secret: String) -> Result<(), DatabaseError> {
+        Err(DatabaseError::NoPassword)
+    }
+
+    /// Tells whether the database saves the changes of single applications by itself
+    /// rather than saving them all, so that they're worth saving one by one.
+    /// The default implementation doesn't.
+    fn supports_row_updates(&self) -> bool {
+        false
+    }
+
+    /// Saves a new application. `applications` holds all of them once every pending change is saved,
+    /// the default implementation saves them all.
+    fn insert_application(
+        &self,
+        _app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.save_applications(applications)
+    }
+
+    /// Replaces the application saved as `name`, which may have been renamed.
+    /// `applications` holds all of them once every pending change is saved,
+    /// the default implementation saves them all.
+    fn update_application(
+        &self,
+        _name: &str,
+        _app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.save_applications(applications)
+    }
+
+    /// Removes the application saved as `name`.
+    /// `applications` holds all of them once every pending change is saved,
+    /// the default implementation saves them all.
+    fn remove_application(
+        &self,
+        _name: &str,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.save_applications(applications)
+    }
 }
 
 macro_rules! impl_database_trait {
     ($type:ty) => {
         impl Database for $type {
-            fn get_applications(&self) -> HashMap<String, GenApp> {
-                let db_content = self.read_database_file();
-                db_content.content.applications
+            fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError> {
+                let db_content = self.read_database_file()?;
+                Ok(db_content.content.applications)
+            }
+
+            fn save_applications(
+                &self,
+                applications: &HashMap<String, GenApp>,
+            ) -> Result<(), DatabaseError> {
+                self.save_database_file(JsonDatabaseSchema::new(applications.clone()))
             }
 
-            fn save_applications(&self, applications: &HashMap<String, GenApp>) {
-                let mut db_content = Self::get_empty_schema();
-                db_content.content.applications = applications.clone();
-                self.save_database_file(db_content);
+            fn set_secret(&mut self, secret: String) -> Result<(), DatabaseError> {
+                self.secret = secret;
+                Ok(())
             }
         }
     };
@@ -129,105 +332,96 @@ macro_rules! impl_database_trait {
 impl_database_trait!(JsonDatabase);
 impl_database_trait!(AgeJsonDatabase);
 
-#[derive(Serialize, Deserialize)]
-pub struct JsonDatabaseSchema {
-    version: u8,
-    content: DatabaseContentSchema,
-}
-
-#[derive(Serialize, Deserialize)]
-struct DatabaseContentSchema {
-    applications: HashMap<String, GenApp>,
-}
-
 pub struct JsonDatabase {
     file_path: PathBuf,
     secret: String,
+    loaded_fingerprint: RefCell<Option<Vec<u8>>>,
 }
 
 pub struct AgeJsonDatabase {
     file_path: PathBuf,
     secret: String,
+    loaded_fingerprint: RefCell<Option<Vec<u8>>>,
 }
 
 const IV_SIZE: usize = 16;
 const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const KEY_CHECK_SIZE: usize = 32;
+const KEY_CHECK_LABEL: &[u8] = b"RusTOTPony key check";
+const SALT_SIZE: usize = 16;
+// scrypt parameters in the header: log2(N), r and p, followed by the salt
+const KDF_PARAMS_SIZE: usize = 1 + 4 + 4 + SALT_SIZE;
+// Cost of the key derivation for newly written files.
+// It's stored in every file, so it can be raised without breaking older files.
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 17;
+// Keeps the tests writing databases fast
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 10;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+// Highest cost accepted from a file, as its header is read before the password is checked:
+// 1 GiB of memory and four times the time of that
+const SCRYPT_MAX_MEMORY: u64 = 1 << 30;
+const SCRYPT_MAX_P: u32 = 4;
+// Version of the AES file format written after the RusTOTPony header:
+// AES-256-GCM with a key derived by scrypt and a key check value.
+// Versions 1 and 2 were never released.
+const AES_SCRYPT_VERSION: u8 = 3;
+
 pub trait JsonDatabaseTrait {
+    const FORMAT: VaultFormat;
+
     fn get_file_path(&self) -> &PathBuf;
     fn get_secret(&self) -> String;
+    /// Fingerprint of the file as it was last read or written, `None` before that
+    fn get_loaded_fingerprint(&self) -> &RefCell<Option<Vec<u8>>>;
 
     fn new(path: PathBuf, secret: String) -> Self;
 
-    fn encrypt_data(data: &str, key: &str) -> Vec<u8>;
-
-    fn decrypt_data(data: &[u8], key: &str) -> String;
+    fn encrypt_data(data: &str, key: &str) -> Result<Vec<u8>, DatabaseError>;
 
-    fn read_database_file(&self) -> JsonDatabaseSchema {
-        let data = match std::fs::read(self.get_file_path()) {
-            Ok(d) => d,
-            Err(ref err) if err.kind() == ErrorKind::NotFound => return Self::get_empty_schema(),
-            Err(err) => panic!("There was a problem opening file: {:?}", err),
-        };
-        let decrypted_data = Self::decrypt_data(&data, self.get_secret().as_str());
-        serde_json::from_str(decrypted_data.as_str())
-            .expect("Couldn't parse JSON from database file")
-    }
-
-    fn create_iv() -> Vec<u8> {
-        let mut iv = vec![0; IV_SIZE];
-        let mut rng = rand::thread_rng();
-        rng.fill_bytes(&mut iv);
-        iv
-    }
+    fn decrypt_data(data: &[u8], key: &str) -> Result<String, DatabaseError>;
 
-    fn save_database_file(&self, content: JsonDatabaseSchema) {
-        let mut file = match self.open_database_file_for_write() {
-            Ok(f) => f,
-            Err(ref err) if err.kind() == ErrorKind::NotFound => self
-                .create_database_file()
-                .expect("Couldn't create database file"),
-            Err(err) => panic!("Couldn't open database file: {:?}", err),
+    fn read_database_file(&self) -> Result<JsonDatabaseSchema, DatabaseError> {
+        let path = self.get_file_path();
+        let data = match read_tracked(path, self.get_loaded_fingerprint())? {
+            Some(d) => d,
+            None => return Ok(Self::get_empty_schema()),
         };
-        let data = serde_json::to_string(&content).expect("Couldn't serialize data to JSON");
-        let encrypted_data = Self::encrypt_data(&data, self.get_secret().as_str());
-        file.write_all(&encrypted_data)
-            .expect("Couldn't write data to database file");
+        VaultFormat::check(path, &data, Self::FORMAT)?;
+        let decrypted_data = Self::decrypt_data(&data, self.get_secret().as_str())?;
+        JsonDatabaseSchema::parse(&decrypted_data)
     }
 
-    fn create_database_file(&self) -> Result<File, std::io::Error> {
-        let dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-        if let Some(parent_dir) = Path::new(&self.get_file_path()).parent() {
-            let dir = dir.join(parent_dir);
-            create_dir_all(dir)?;
-        }
-        self.open_database_file_for_write()
-    }
-
-    fn open_database_file_for_write(&self) -> Result<File, std::io::Error> {
-        OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(self.get_file_path())
+    /// Refuses to overwrite the file if another process changed it since it was loaded
+    fn save_database_file(&self, content: JsonDatabaseSchema) -> Result<(), DatabaseError> {
+        let data = content.to_json()?;
+        let encrypted_data = Self::encrypt_data(&data, self.get_secret().as_str())?;
+        write_tracked(
+            self.get_file_path(),
+            self.get_loaded_fingerprint(),
+            &encrypted_data,
+        )
     }
 
     fn get_empty_schema() -> JsonDatabaseSchema {
-        JsonDatabaseSchema {
-            version: DATABASE_VERSION,
-            content: DatabaseContentSchema {
-                applications: HashMap::new(),
-            },
-        }
+        JsonDatabaseSchema::new(HashMap::new())
     }
 }
 
 macro_rules! impl_json_database_trait {
-    ($type:ty) => {
+    ($type:ty, $format:expr) => {
         impl JsonDatabaseTrait for $type {
+            const FORMAT: VaultFormat = $format;
+
             fn new(path: PathBuf, secret: String) -> Self {
                 Self {
                     file_path: path,
                     secret,
+                    loaded_fingerprint: RefCell::new(None),
                 }
             }
 
@@ -239,19 +433,23 @@ macro_rules! impl_json_database_trait {
                 self.secret.clone()
             }
 
-            fn encrypt_data(data: &str, key: &str) -> Vec<u8> {
+            fn get_loaded_fingerprint(&self) -> &RefCell<Option<Vec<u8>>> {
+                &self.loaded_fingerprint
+            }
+
+            fn encrypt_data(data: &str, key: &str) -> Result<Vec<u8>, DatabaseError> {
                 <$type>::encrypt_data(data, key)
             }
 
-            fn decrypt_data(data: &[u8], key: &str) -> String {
+            fn decrypt_data(data: &[u8], key: &str) -> Result<String, DatabaseError> {
                 <$type>::decrypt_data(data, key)
             }
         }
     };
 }
 
-impl_json_database_trait!(JsonDatabase);
-impl_json_database_trait!(AgeJsonDatabase);
+impl_json_database_trait!(JsonDatabase, VaultFormat::Aes);
+impl_json_database_trait!(AgeJsonDatabase, VaultFormat::Age);
 
 impl JsonDatabase {
     fn form_secret_key(input: &str) -> [u8; KEY_SIZE] {
@@ -260,86 +458,129 @@ impl JsonDatabase {
         hasher.finalize().into()
     }
 
-    fn encrypt_data(data: &str, key: &str) -> Vec<u8> {
-        let key = Self::form_secret_key(key);
-        let iv = Self::create_iv();
-        let encrypted_data =
-            Self::encrypt(data.as_bytes(), &key, &iv).expect("Couldn't encrypt data");
-        [&iv, &encrypted_data[..]].concat()
+    // Files are always written in the latest format,
+    // so older ones are upgraded on the next save
+    fn encrypt_data(data: &str, key: &str) -> Result<Vec<u8>, DatabaseError> {
+        let mut rng = rand::thread_rng();
+        let kdf_params = Self::new_kdf_params();
+        let key = Self::derive_secret_key(key, &kdf_params)?;
+        let mut nonce = [0; NONCE_SIZE];
+        rng.fill_bytes(&mut nonce);
+        // The header is authenticated along with the data
+        let header = [
+            vault::AES_MAGIC,
+            &[AES_SCRYPT_VERSION],
+            &kdf_params,
+            &Self::key_check(&key),
+            &nonce,
+        ]
+        .concat();
+        let mut encrypted_data = vec![0; data.len()];
+        let mut tag = [0; TAG_SIZE];
+        AesGcm::new(aes::KeySize::KeySize256, &key, &nonce, &header).encrypt(
+            data.as_bytes(),
+            &mut encrypted_data,
+            &mut tag,
+        );
+        Ok([&header[..], &encrypted_data, &tag].concat())
     }
 
-    fn decrypt_data(data: &[u8], key: &str) -> String {
-        let key = Self::form_secret_key(key);
-        let iv = &data[..IV_SIZE];
-        String::from_utf8(Self::decrypt(&data[IV_SIZE..], &key, iv).expect("Couldn't decrypt data"))
-            .ok()
-            .unwrap()
+    fn decrypt_data(data: &[u8], key: &str) -> Result<String, DatabaseError> {
+        // Files written before the header was introduced start right with the IV
+        let rest = match data.strip_prefix(vault::AES_MAGIC) {
+            Some(rest) => rest,
+            None => return Self::decrypt_cbc(data, &Self::form_secret_key(key)),
+        };
+        match rest.first() {
+            Some(&AES_SCRYPT_VERSION) => {
+                let kdf_params = rest
+                    .get(1..1 + KDF_PARAMS_SIZE)
+                    .ok_or(DatabaseError::Corrupt)?;
+                let key = Self::derive_secret_key(key, kdf_params)?;
+                Self::decrypt_gcm(data, &key)
+            }
+            _ => Err(DatabaseError::UnsupportedVersion),
+        }
     }
 
-    // Encrypt a buffer with the given key and iv using
-    // AES-256/CBC/Pkcs encryption.
-    fn encrypt(
-        data: &[u8],
-        key: &[u8],
-        iv: &[u8],
-    ) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
-        // Create an encryptor instance of the best performing
-        // type available for the platform.
-        let mut encryptor =
-            aes::cbc_encryptor(aes::KeySize::KeySize256, key, iv, blockmodes::PkcsPadding);
-
-        // Each encryption operation encrypts some data from
-        // an input buffer into an output buffer. Those buffers
-        // must be instances of RefReaderBuffer and RefWriteBuffer
-        // (respectively) which keep track of how much data has been
-        // read from or written to them.
-        let mut final_result = Vec::<u8>::new();
-        let mut read_buffer = buffer::RefReadBuffer::new(data);
-        let mut buffer = [0; 4096];
-        let mut write_buffer = buffer::RefWriteBuffer::new(&mut buffer);
+    // scrypt parameters with the current cost and a new random salt
+    fn new_kdf_params() -> Vec<u8> {
+        let mut salt = [0; SALT_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        [
+            &[SCRYPT_LOG_N][..],
+            &SCRYPT_R.to_be_bytes(),
+            &SCRYPT_P.to_be_bytes(),
+            &salt,
+        ]
+        .concat()
+    }
 
-        // Each encryption operation will "make progress". "Making progress"
-        // is a bit loosely defined, but basically, at the end of each operation
-        // either BufferUnderflow or BufferOverflow will be returned (unless
-        // there was an error). If the return value is BufferUnderflow, it means
-        // that the operation ended while wanting more input data. If the return
-        // value is BufferOverflow, it means that the operation ended because it
-        // needed more space to output data. As long as the next call to the encryption
-        // operation provides the space that was requested (either more input data
-        // or more output space), the operation is guaranteed to get closer to
-        // completing the full operation - ie: "make progress".
-        //
-        // Here, we pass the data to encrypt to the enryptor along with a fixed-size
-        // output buffer. The 'true' flag indicates that the end of the data that
-        // is to be encrypted is included in the input buffer (which is true, since
-        // the input data includes all the data to encrypt). After each call, we copy
-        // any output data to our result Vec. If we get a BufferOverflow, we keep
-        // going in the loop since it means that there is more work to do. We can
-        // complete as soon as we get a BufferUnderflow since the encryptor is telling
-        // us that it stopped processing data due to not having any more data in the
-        // input buffer.
-        loop {
-            let result = encryptor.encrypt(&mut read_buffer, &mut write_buffer, true)?;
+    fn derive_secret_key(input: &str, kdf_params: &[u8]) -> Result<[u8; KEY_SIZE], DatabaseError> {
+        if kdf_params.len() != KDF_PARAMS_SIZE {
+            return Err(DatabaseError::Corrupt);
+        }
+        let (log_n, rest) = kdf_params.split_first().ok_or(DatabaseError::Corrupt)?;
+        let (r, rest) = rest.split_first_chunk().ok_or(DatabaseError::Corrupt)?;
+        let (p, salt) = rest.split_first_chunk().ok_or(DatabaseError::Corrupt)?;
+        let (r, p) = (u32::from_be_bytes(*r), u32::from_be_bytes(*p));
+        // scrypt needs 128 * r * N bytes
+        let memory = 1u64
+            .checked_shl(u32::from(*log_n))
+            .and_then(|n| n.checked_mul(128 * u64::from(r)));
+        if memory.is_none_or(|memory| memory > SCRYPT_MAX_MEMORY) || p > SCRYPT_MAX_P {
+            return Err(DatabaseError::Corrupt);
+        }
+        let params =
+            scrypt::Params::new(*log_n, r, p, KEY_SIZE).map_err(|_| DatabaseError::Corrupt)?;
+        let mut key = [0; KEY_SIZE];
+        scrypt::scrypt(input.as_bytes(), salt, &params, &mut key)
+            .map_err(|_| DatabaseError::Corrupt)?;
+        Ok(key)
+    }
 
-            // "write_buffer.take_read_buffer().take_remaining()" means:
-            // from the writable buffer, create a new readable buffer which
-            // contains all data that has been written, and then access all
-            // of that data as a slice.
-            final_result.extend(
-                write_buffer
-                    .take_read_buffer()
-                    .take_remaining()
-                    .iter()
-                    .copied(),
-            );
+    // Unauthenticated format: a wrong password and a file that isn't a database
+    // look the same, both usually end up with invalid padding.
+    fn decrypt_cbc(data: &[u8], key: &[u8]) -> Result<String, DatabaseError> {
+        if data.len() < IV_SIZE {
+            return Err(DatabaseError::Corrupt);
+        }
+        let (iv, encrypted_data) = data.split_at(IV_SIZE);
+        let decrypted_data = Self::decrypt(encrypted_data, key, iv)
+            .map_err(|_| DatabaseError::WrongPasswordOrUnknownFile)?;
+        String::from_utf8(decrypted_data).map_err(|_| DatabaseError::WrongPasswordOrUnknownFile)
+    }
 
-            match result {
-                BufferResult::BufferUnderflow => break,
-                BufferResult::BufferOverflow => {}
-            }
+    fn decrypt_gcm(data: &[u8], key: &[u8]) -> Result<String, DatabaseError> {
+        let prefix_size = vault::AES_MAGIC.len() + 1 + KDF_PARAMS_SIZE;
+        let header_size = prefix_size + KEY_CHECK_SIZE + NONCE_SIZE;
+        if data.len() < header_size + TAG_SIZE {
+            return Err(DatabaseError::Corrupt);
+        }
+        let (header, rest) = data.split_at(header_size);
+        let (encrypted_data, tag) = rest.split_at(rest.len() - TAG_SIZE);
+        let (key_check, nonce) = header[prefix_size..].split_at(KEY_CHECK_SIZE);
+        if !fixed_time_eq(key_check, &Self::key_check(key)) {
+            return Err(DatabaseError::WrongPassword);
         }
+        let mut decrypted_data = vec![0; encrypted_data.len()];
+        if !AesGcm::new(aes::KeySize::KeySize256, key, nonce, header).decrypt(
+            encrypted_data,
+            &mut decrypted_data,
+            tag,
+        ) {
+            return Err(DatabaseError::Corrupt);
+        }
+        String::from_utf8(decrypted_data).map_err(|_| DatabaseError::Corrupt)
+    }
 
-        Ok(final_result)
+    // A value stored in the header to tell a wrong password from a damaged file
+    fn key_check(key: &[u8]) -> [u8; KEY_CHECK_SIZE] {
+        let mut hmac = Hmac::new(crypto::sha2::Sha256::new(), key);
+        hmac.input(KEY_CHECK_LABEL);
+        let mut check = [0; KEY_CHECK_SIZE];
+        hmac.raw_result(&mut check);
+        check
     }
 
     // Decrypts a buffer with the given key and iv using
@@ -377,38 +618,54 @@ impl JsonDatabase {
 }
 
 impl AgeJsonDatabase {
-    fn encrypt_data(data: &str, key: &str) -> Vec<u8> {
+    fn encrypt_data(data: &str, key: &str) -> Result<Vec<u8>, DatabaseError> {
         let encryptor =
             age::Encryptor::with_user_passphrase(age::secrecy::Secret::new(key.to_owned()));
 
         let mut encrypted = vec![];
-        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
-        writer.write_all(data.as_bytes()).unwrap();
-        writer.finish().unwrap();
+        let mut writer = encryptor
+            .wrap_output(&mut encrypted)
+            .map_err(|err| DatabaseError::Encryption(err.to_string()))?;
+        writer.write_all(data.as_bytes())?;
+        writer.finish()?;
 
-        encrypted
+        Ok(encrypted)
     }
 
-    fn decrypt_data(data: &[u8], key: &str) -> String {
-        let decryptor = match age::Decryptor::new(data).unwrap() {
-            age::Decryptor::Passphrase(d) => d,
-            _ => unreachable!(),
+    fn decrypt_data(data: &[u8], key: &str) -> Result<String, DatabaseError> {
+        let decryptor = match age::Decryptor::new(data) {
+            Ok(age::Decryptor::Passphrase(d)) => d,
+            Ok(_) => return Err(DatabaseError::UnsupportedVersion),
+            Err(_) => return Err(DatabaseError::Corrupt),
         };
 
         let mut decrypted = vec![];
         let mut reader = decryptor
             .decrypt(&age::secrecy::Secret::new(key.to_owned()), None)
-            .unwrap();
-        reader.read_to_end(&mut decrypted).unwrap();
-
-        String::from_utf8(decrypted).unwrap()
+            .map_err(|err| match err {
+                age::DecryptError::DecryptionFailed
+                | age::DecryptError::KeyDecryptionFailed
+                | age::DecryptError::NoMatchingKeys => DatabaseError::WrongPassword,
+                _ => DatabaseError::Corrupt,
+            })?;
+        reader
+            .read_to_end(&mut decrypted)
+            .map_err(|_| DatabaseError::Corrupt)?;
+
+        String::from_utf8(decrypted).map_err(|_| DatabaseError::Corrupt)
     }
 }
 
+/// An entry read from an external source: its name and either the parsed generator
+/// or the reason it was rejected.
+pub type ImportedEntry = (String, Result<GenApp, String>);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GenApp {
     name: String,
     secret: String,
+    // Missing from generators written by hand or by other tools
+    #[serde(default)]
     username: String,
     secret_bytes: Vec<u8>,
 }
@@ -423,6 +680,18 @@ impl GenApp {
         }
     }
 
+    /// Creates a generator from a base32-encoded secret.
+    /// Whitespace and padding are stripped and the secret is uppercased.
+    pub fn from_secret(name: &str, username: &str, secret: &str) -> Option<Self> {
+        let secret: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect::<String>()
+            .to_uppercase();
+        let secret_bytes = Self::base32_to_bytes(&secret)?;
+        Some(Self::new(name, username, &secret, secret_bytes))
+    }
+
     pub fn get_name(&self) -> &str {
         self.name.as_str()
     }
@@ -451,3 +720,465 @@ impl GenApp {
         totp_custom::<Sha1>(DEFAULT_STEP, 6, secret_bytes, seconds)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::GenApp;
+
+    use std::collections::HashMap;
+
+    /// Secret of the generators created by tests
+    pub(crate) const SECRET: &str = "GEZDGMZSGE2TKNIK";
+
+    /// Generators without username named after `names`
+    pub(crate) fn applications(names: &[&str]) -> HashMap<String, GenApp> {
+        names
+            .iter()
+            .map(|name| {
+                let app = GenApp::from_secret(name, "", SECRET).unwrap();
+                (String::from(*name), app)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SECRET;
+
+    fn saved_names<DB: Database>(app: &RusTOTPony<DB>) -> Vec<String> {
+        let mut names: Vec<_> = app
+            .get_database()
+            .get_applications()
+            .unwrap()
+            .into_keys()
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn loads_the_applications_of_the_database() {
+        let mut applications = HashMap::new();
+        applications.insert(
+            String::from("github"),
+            GenApp::from_secret("github", "alice", SECRET).unwrap(),
+        );
+        let app = RusTOTPony::new(MemoryDatabase::from(applications)).unwrap();
+        let github = app.get_application("github").unwrap();
+        assert_eq!(github.get_username(), "alice");
+        assert_eq!(github.get_code().len(), 6);
+    }
+
+    #[test]
+    fn saves_changes_on_flush() {
+        let mut app = RusTOTPony::new(MemoryDatabase::new()).unwrap();
+        app.create_application("github", "alice", SECRET).unwrap();
+        app.create_application("gitlab", "", SECRET).unwrap();
+        assert!(saved_names(&app).is_empty());
+        app.flush().unwrap();
+        assert_eq!(saved_names(&app), ["github", "gitlab"]);
+        assert_eq!(app.get_application("gitlab").unwrap().get_secret(), SECRET);
+
+        app.rename_application("github", "work/github").unwrap();
+        app.delete_application("gitlab").unwrap();
+        app.flush().unwrap();
+        assert_eq!(saved_names(&app), ["work/github"]);
+        let saved = app.get_database().get_applications().unwrap();
+        assert_eq!(saved["work/github"].get_name(), "work/github");
+        assert_eq!(saved["work/github"].get_username(), "alice");
+
+        app.delete_all_applications();
+        app.flush().unwrap();
+        assert!(saved_names(&app).is_empty());
+    }
+
+    /// Database recording how the applications were saved
+    #[derive(Default)]
+    struct RecordingDatabase {
+        saves: RefCell<Vec<String>>,
+        row_updates: bool,
+        // Fails the saves after this many ones
+        failing_after: Option<usize>,
+    }
+
+    impl RecordingDatabase {
+        fn with_row_updates() -> Self {
+            RecordingDatabase {
+                row_updates: true,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl RecordingDatabase {
+        fn save(&self, save: String) -> Result<(), DatabaseError> {
+            let mut saves = self.saves.borrow_mut();
+            if self.failing_after.is_some_and(|count| saves.len() >= count) {
+                return Err(DatabaseError::ConcurrentModification);
+            }
+            saves.push(save);
+            Ok(())
+        }
+    }
+
+    impl Database for RecordingDatabase {
+        fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError> {
+            Ok(HashMap::new())
+        }
+
+        fn save_applications(
+            &self,
+            applications: &HashMap<String, GenApp>,
+        ) -> Result<(), DatabaseError> {
+            self.save(format!("save {}", applications.len()))
+        }
+
+        fn supports_row_updates(&self) -> bool {
+            self.row_updates
+        }
+
+        fn insert_application(
+            &self,
+            app: &GenApp,
+            _applications: &HashMap<String, GenApp>,
+        ) -> Result<(), DatabaseError> {
+            self.save(format!("insert {} {}", app.name, app.username))
+        }
+
+        fn update_application(
+            &self,
+            name: &str,
+            app: &GenApp,
+            _applications: &HashMap<String, GenApp>,
+        ) -> Result<(), DatabaseError> {
+            self.save(format!("update {} {}", name, app.name))
+        }
+
+        fn remove_application(
+            &self,
+            name: &str,
+            _applications: &HashMap<String, GenApp>,
+        ) -> Result<(), DatabaseError> {
+            self.save(format!("remove {}", name))
+        }
+    }
+
+    fn saves(app: &RusTOTPony<RecordingDatabase>) -> Vec<String> {
+        app.get_database().saves.take()
+    }
+
+    #[test]
+    fn saves_each_change_in_order() {
+        let mut app = RusTOTPony::new(RecordingDatabase::with_row_updates()).unwrap();
+        app.flush().unwrap();
+        assert!(saves(&app).is_empty());
+
+        app.create_application("github", "alice", SECRET).unwrap();
+        app.rename_application("github", "work/github").unwrap();
+        app.create_application("gitlab", "bob", SECRET).unwrap();
+        app.delete_application("gitlab").unwrap();
+        app.rename_application("work/github", "github").unwrap();
+        app.flush().unwrap();
+        assert_eq!(
+            saves(&app),
+            [
+                "insert github alice",
+                "update github work/github",
+                "insert gitlab bob",
+                "remove gitlab",
+                "update work/github github",
+            ]
+        );
+        app.flush().unwrap();
+        assert!(saves(&app).is_empty());
+    }
+
+    #[test]
+    fn saves_once_without_row_updates() {
+        let mut app = RusTOTPony::new(RecordingDatabase::default()).unwrap();
+        app.create_application("github", "", SECRET).unwrap();
+        app.create_application("gitlab", "", SECRET).unwrap();
+        app.rename_application("github", "work/github").unwrap();
+        app.flush().unwrap();
+        assert_eq!(saves(&app), ["save 2"]);
+        app.flush().unwrap();
+        assert!(saves(&app).is_empty());
+    }
+
+    #[test]
+    fn saves_every_application_at_once() {
+        let mut app = RusTOTPony::new(RecordingDatabase::with_row_updates()).unwrap();
+        app.create_application("github", "", SECRET).unwrap();
+        app.flush_all().unwrap();
+        assert_eq!(saves(&app), ["save 1"]);
+        app.flush_all().unwrap();
+        assert_eq!(saves(&app), ["save 1"]);
+
+        app.create_application("gitlab", "", SECRET).unwrap();
+        app.delete_all_applications();
+        app.create_application("bitbucket", "", SECRET).unwrap();
+        app.flush().unwrap();
+        assert_eq!(saves(&app), ["save 1"]);
+        app.flush().unwrap();
+        assert!(saves(&app).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_changes_that_failed() {
+        let db = RecordingDatabase {
+            failing_after: Some(1),
+            ..RecordingDatabase::with_row_updates()
+        };
+        let mut app = RusTOTPony::new(db).unwrap();
+        app.create_application("github", "", SECRET).unwrap();
+        app.create_application("gitlab", "", SECRET).unwrap();
+        assert!(app.flush().is_err());
+        assert_eq!(saves(&app), ["insert github "]);
+
+        app.database.failing_after = None;
+        app.flush().unwrap();
+        assert_eq!(saves(&app), ["insert gitlab "]);
+    }
+
+    #[test]
+    fn reports_invalid_changes() {
+        let mut app = RusTOTPony::new(MemoryDatabase::new()).unwrap();
+        app.create_application("github", "", SECRET).unwrap();
+        app.create_application("gitlab", "", SECRET).unwrap();
+        assert_eq!(
+            app.create_application("github", "", SECRET),
+            Err(ApplicationError::AlreadyExists(String::from("github")))
+        );
+        assert_eq!(
+            app.create_application("bitbucket", "", "not base32!"),
+            Err(ApplicationError::InvalidSecret)
+        );
+        assert_eq!(
+            app.rename_application("missing", "github"),
+            Err(ApplicationError::NotFound(String::from("missing")))
+        );
+        assert_eq!(
+            app.rename_application("github", "gitlab"),
+            Err(ApplicationError::AlreadyExists(String::from("gitlab")))
+        );
+        assert_eq!(
+            app.delete_application("missing"),
+            Err(ApplicationError::NotFound(String::from("missing")))
+        );
+        app.flush().unwrap();
+        assert_eq!(saved_names(&app), ["github", "gitlab"]);
+        app.rename_application("github", "github").unwrap();
+        app.flush().unwrap();
+        assert_eq!(saved_names(&app), ["github", "gitlab"]);
+    }
+
+    #[test]
+    fn matches_names_with_patterns() {
+        let mut app = RusTOTPony::new(MemoryDatabase::new()).unwrap();
+        for name in ["work/github", "work/jira", "github"] {
+            app.create_application(name, "", SECRET).unwrap();
+        }
+        let names = |patterns: &[&str]| {
+            let patterns: Vec<_> = patterns.iter().map(|p| String::from(*p)).collect();
+            let mut names: Vec<_> = app
+                .get_applications_matching(&patterns)
+                .unwrap()
+                .into_keys()
+                .collect();
+            names.sort();
+            names.join(" ")
+        };
+        assert_eq!(names(&[]), "github work/github work/jira");
+        assert_eq!(names(&["work/*"]), "work/github work/jira");
+        assert_eq!(names(&["*github", "nothing"]), "github work/github");
+        assert!(matches!(
+            app.get_applications_matching(&[String::from("[")]),
+            Err(ApplicationError::InvalidPattern(..))
+        ));
+    }
+
+    #[test]
+    fn refuses_to_change_a_missing_password() {
+        let mut app = RusTOTPony::new(MemoryDatabase::new()).unwrap();
+        assert!(matches!(
+            app.change_password(String::from("password")),
+            Err(DatabaseError::NoPassword)
+        ));
+    }
+
+    const CONTENT: &str = r#"{"version":1,"content":{"applications":{}}}"#;
+
+    fn create_iv() -> Vec<u8> {
+        let mut iv = vec![0; IV_SIZE];
+        rand::thread_rng().fill_bytes(&mut iv);
+        iv
+    }
+
+    // Encrypts like the files written before AES-GCM: an IV followed by AES-256-CBC blocks
+    fn encrypt_cbc(data: &str, password: &str) -> Vec<u8> {
+        let iv = create_iv();
+        let key = JsonDatabase::form_secret_key(password);
+        let mut encryptor =
+            aes::cbc_encryptor(aes::KeySize::KeySize256, &key, &iv, blockmodes::PkcsPadding);
+        let mut encrypted = iv.clone();
+        let mut read_buffer = buffer::RefReadBuffer::new(data.as_bytes());
+        let mut output = [0; 4096];
+        let mut write_buffer = buffer::RefWriteBuffer::new(&mut output);
+        encryptor
+            .encrypt(&mut read_buffer, &mut write_buffer, true)
+            .unwrap();
+        encrypted.extend(write_buffer.take_read_buffer().take_remaining());
+        encrypted
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypts() {
+        let data = JsonDatabase::encrypt_data(CONTENT, "password").unwrap();
+        assert!(data.starts_with(&[vault::AES_MAGIC, &[AES_SCRYPT_VERSION]].concat()));
+        assert_eq!(
+            JsonDatabase::decrypt_data(&data, "password").unwrap(),
+            CONTENT
+        );
+        assert_ne!(
+            JsonDatabase::encrypt_data(CONTENT, "password").unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn tells_a_wrong_password_from_a_damaged_file() {
+        let data = JsonDatabase::encrypt_data(CONTENT, "password").unwrap();
+        assert!(matches!(
+            JsonDatabase::decrypt_data(&data, "wrong"),
+            Err(DatabaseError::WrongPassword)
+        ));
+        let header_size = vault::AES_MAGIC.len() + 1 + KDF_PARAMS_SIZE + KEY_CHECK_SIZE;
+        // The nonce, the encrypted data and the tag
+        for position in [header_size, header_size + NONCE_SIZE + 1, data.len() - 1] {
+            let mut tampered = data.clone();
+            tampered[position] ^= 1;
+            assert!(
+                matches!(
+                    JsonDatabase::decrypt_data(&tampered, "password"),
+                    Err(DatabaseError::Corrupt)
+                ),
+                "{}",
+                position
+            );
+        }
+        assert!(matches!(
+            JsonDatabase::decrypt_data(&data[..data.len() - TAG_SIZE], "password"),
+            Err(DatabaseError::Corrupt)
+        ));
+        for version in [1, 2, AES_SCRYPT_VERSION + 1] {
+            let mut unknown_version = data.clone();
+            unknown_version[vault::AES_MAGIC.len()] = version;
+            assert!(matches!(
+                JsonDatabase::decrypt_data(&unknown_version, "password"),
+                Err(DatabaseError::UnsupportedVersion)
+            ));
+        }
+    }
+
+    #[test]
+    fn decrypts_files_written_before_the_header() {
+        let legacy = encrypt_cbc(CONTENT, "password");
+        assert_eq!(VaultFormat::detect(&legacy), Some(VaultFormat::Aes));
+        assert_eq!(
+            JsonDatabase::decrypt_data(&legacy, "password").unwrap(),
+            CONTENT
+        );
+        assert!(matches!(
+            JsonDatabase::decrypt_data(&legacy, "wrong"),
+            Err(DatabaseError::WrongPasswordOrUnknownFile)
+        ));
+    }
+
+    #[test]
+    fn upgrades_legacy_files_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        let mut applications = HashMap::new();
+        applications.insert(
+            String::from("github"),
+            GenApp::from_secret("github", "alice", SECRET).unwrap(),
+        );
+        let content = JsonDatabaseSchema::new(applications).to_json().unwrap();
+        std::fs::write(&path, encrypt_cbc(&content, "password")).unwrap();
+        let db = JsonDatabase::new(path.clone(), String::from("password"));
+        let applications = db.get_applications().unwrap();
+        assert_eq!(applications["github"].get_username(), "alice");
+        db.save_applications(&applications).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data[vault::AES_MAGIC.len()], AES_SCRYPT_VERSION);
+        let reopened = JsonDatabase::new(path, String::from("password"));
+        let github = &reopened.get_applications().unwrap()["github"];
+        assert_eq!(github.get_secret(), SECRET);
+        assert_eq!(github.get_username(), "alice");
+    }
+
+    fn kdf_params(log_n: u8, r: u32, p: u32) -> Vec<u8> {
+        [
+            &[log_n][..],
+            &r.to_be_bytes(),
+            &p.to_be_bytes(),
+            &[0; SALT_SIZE],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn derives_keys_with_the_stored_parameters() {
+        let key = JsonDatabase::derive_secret_key("password", &kdf_params(10, 8, 1)).unwrap();
+        assert_eq!(
+            key,
+            JsonDatabase::derive_secret_key("password", &kdf_params(10, 8, 1)).unwrap()
+        );
+        assert_ne!(
+            key,
+            JsonDatabase::derive_secret_key("password", &kdf_params(11, 8, 1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_costly_or_malformed_parameters() {
+        for params in [
+            kdf_params(21, 8, 1),
+            kdf_params(20, 9, 1),
+            kdf_params(10, 8, 5),
+            kdf_params(63, 1, 1),
+            kdf_params(64, 1, 1),
+            kdf_params(255, u32::MAX, u32::MAX),
+            kdf_params(10, 0, 1),
+            kdf_params(10, 8, 1)[..KDF_PARAMS_SIZE - 1].to_vec(),
+            vec![],
+        ] {
+            assert!(
+                matches!(
+                    JsonDatabase::derive_secret_key("password", &params),
+                    Err(DatabaseError::Corrupt)
+                ),
+                "{:?}",
+                params
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_files_with_costly_parameters() {
+        let data = [
+            vault::AES_MAGIC,
+            &[AES_SCRYPT_VERSION],
+            &kdf_params(40, 8, 1),
+            &[0; KEY_CHECK_SIZE + NONCE_SIZE + TAG_SIZE],
+        ]
+        .concat();
+        assert!(matches!(
+            JsonDatabase::decrypt_data(&data, "password"),
+            Err(DatabaseError::Corrupt)
+        ));
+    }
+}
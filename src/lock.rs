@@ -0,0 +1,189 @@
+use super::atomic;
+use super::{sidecar_path, DatabaseError};
+
+use sha2::{Digest, Sha256};
+
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Advisory lock of a database file, released when dropped.
+///
+/// The lock is held on a `.lock` file next to the database,
+/// because atomic writes replace the database file itself.
+pub(crate) struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Waits until no other process writes the file.
+    ///
+    /// Reading creates nothing: without a lock file, or one that can't be opened, no lock is taken
+    /// and `None` is returned. Writes replace the file atomically, so it's still read whole.
+    pub(crate) fn shared(path: &Path) -> io::Result<Option<Self>> {
+        let file = match File::open(sidecar_path(path, "lock")) {
+            Ok(file) => file,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::NotFound | ErrorKind::PermissionDenied
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        file.lock_shared()?;
+        Ok(Some(FileLock { _file: file }))
+    }
+
+    /// Waits until no other process reads or writes the file
+    pub(crate) fn exclusive(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(sidecar_path(path, "lock"))?;
+        file.lock()?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+/// Identifies the content of a database file, a missing file has the fingerprint of no data
+fn fingerprint(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// Reads a database file under a shared lock and remembers its fingerprint.
+/// Returns `None` if the file doesn't exist.
+pub(crate) fn read_tracked(
+    path: &Path,
+    loaded_fingerprint: &RefCell<Option<Vec<u8>>>,
+) -> Result<Option<Vec<u8>>, DatabaseError> {
+    let _lock = FileLock::shared(path)?;
+    let data = read_if_exists(path)?;
+    loaded_fingerprint.replace(Some(fingerprint(data.as_deref().unwrap_or_default())));
+    Ok(data)
+}
+
+/// Replaces a database file under an exclusive lock and remembers its fingerprint.
+/// Refuses to overwrite the file if another process changed it since it was loaded.
+pub(crate) fn write_tracked(
+    path: &Path,
+    loaded_fingerprint: &RefCell<Option<Vec<u8>>>,
+    data: &[u8],
+) -> Result<(), DatabaseError> {
+    let _lock = FileLock::exclusive(path)?;
+    if let Some(loaded) = loaded_fingerprint.borrow().as_ref() {
+        let current = read_if_exists(path)?;
+        if &fingerprint(current.as_deref().unwrap_or_default()) != loaded {
+            return Err(DatabaseError::ConcurrentModification);
+        }
+    }
+    atomic::write_file(path, data)?;
+    loaded_fingerprint.replace(Some(fingerprint(data)));
+    Ok(())
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked() -> RefCell<Option<Vec<u8>>> {
+        RefCell::new(None)
+    }
+
+    #[test]
+    fn refuses_to_overwrite_changes_of_another_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        let (first, second) = (tracked(), tracked());
+        assert_eq!(read_tracked(&path, &first).unwrap(), None);
+        assert_eq!(read_tracked(&path, &second).unwrap(), None);
+
+        write_tracked(&path, &second, b"second").unwrap();
+        assert!(matches!(
+            write_tracked(&path, &first, b"first"),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+
+        assert_eq!(
+            read_tracked(&path, &first).unwrap().as_deref(),
+            Some(&b"second"[..])
+        );
+        write_tracked(&path, &first, b"first").unwrap();
+        write_tracked(&path, &first, b"again").unwrap();
+        assert!(matches!(
+            write_tracked(&path, &second, b"second"),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+    }
+
+    #[test]
+    fn refuses_to_recreate_a_removed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        fs::write(&path, b"saved").unwrap();
+        let loaded = tracked();
+        read_tracked(&path, &loaded).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            write_tracked(&path, &loaded, b"data"),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+    }
+
+    #[test]
+    fn reads_without_creating_anything() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("typo").join("totp.safe");
+        assert_eq!(read_tracked(&missing, &tracked()).unwrap(), None);
+        assert!(!missing.parent().unwrap().exists());
+
+        let path = dir.path().join("totp.safe.1700000000000.bak");
+        fs::write(&path, b"saved").unwrap();
+        assert_eq!(
+            read_tracked(&path, &tracked()).unwrap().as_deref(),
+            Some(&b"saved"[..])
+        );
+        assert!(!sidecar_path(&path, "lock").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_files_in_read_only_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        write_tracked(&path, &tracked(), b"saved").unwrap();
+        let lock = sidecar_path(&path, "lock");
+        fs::set_permissions(&lock, fs::Permissions::from_mode(0o000)).unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o555)).unwrap();
+        let data = read_tracked(&path, &tracked());
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(data.unwrap().as_deref(), Some(&b"saved"[..]));
+    }
+
+    #[test]
+    fn writes_files_that_were_never_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        fs::write(&path, b"saved").unwrap();
+        write_tracked(&path, &tracked(), b"data").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+    }
+}
@@ -0,0 +1,81 @@
+use super::{Database, DatabaseError, GenApp};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Database kept in memory, for using `RusTOTPony` without a file and a password.
+///
+/// Saved applications are kept until the database is dropped.
+#[derive(Default)]
+pub struct MemoryDatabase {
+    applications: RefCell<HashMap<String, GenApp>>,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a database holding these applications
+    pub fn with_applications(applications: HashMap<String, GenApp>) -> Self {
+        MemoryDatabase {
+            applications: RefCell::new(applications),
+        }
+    }
+}
+
+impl From<HashMap<String, GenApp>> for MemoryDatabase {
+    fn from(applications: HashMap<String, GenApp>) -> Self {
+        Self::with_applications(applications)
+    }
+}
+
+impl Database for MemoryDatabase {
+    fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError> {
+        Ok(self.applications.borrow().clone())
+    }
+
+    fn save_applications(
+        &self,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.applications.replace(applications.clone());
+        Ok(())
+    }
+
+    fn supports_row_updates(&self) -> bool {
+        true
+    }
+
+    fn insert_application(
+        &self,
+        app: &GenApp,
+        _applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.applications
+            .borrow_mut()
+            .insert(app.name.clone(), app.clone());
+        Ok(())
+    }
+
+    fn update_application(
+        &self,
+        name: &str,
+        app: &GenApp,
+        _applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        let mut applications = self.applications.borrow_mut();
+        applications.remove(name);
+        applications.insert(app.name.clone(), app.clone());
+        Ok(())
+    }
+
+    fn remove_application(
+        &self,
+        name: &str,
+        _applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.applications.borrow_mut().remove(name);
+        Ok(())
+    }
+}
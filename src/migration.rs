@@ -0,0 +1,366 @@
+use super::otpauth::percent_decode;
+use super::GenApp;
+
+use base64::Engine;
+
+const SCHEME: &str = "otpauth-migration://offline?";
+
+// Values of the OtpParameters enums in Google Authenticator's MigrationPayload message
+const ALGORITHM_UNSPECIFIED: u64 = 0;
+const ALGORITHM_SHA1: u64 = 1;
+const DIGITS_UNSPECIFIED: u64 = 0;
+const DIGITS_SIX: u64 = 1;
+const TYPE_UNSPECIFIED: u64 = 0;
+const TYPE_TOTP: u64 = 2;
+
+/// Decodes an `otpauth-migration://offline?data=...` URI exported by Google Authenticator.
+///
+/// Returns one result per generator found in the payload, or an error if the payload itself
+/// can't be decoded.
+pub fn parse_migration_uri(uri: &str) -> Result<Vec<Result<GenApp, String>>, String> {
+    let uri = uri.trim();
+    let query = match uri.get(..SCHEME.len()) {
+        Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+        _ => return Err(String::from("Not an otpauth-migration URI")),
+    };
+    let data = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("data="))
+        .ok_or_else(|| String::from("Missing migration data"))?;
+    let data = percent_decode(data)?;
+    let payload = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| format!("Invalid migration data: {}", e))?;
+
+    let mut reader = ProtoReader::new(&payload);
+    let mut applications = vec![];
+    while let Some((field, value)) = reader.next_field()? {
+        if let (1, ProtoValue::Bytes(parameters)) = (field, value) {
+            applications.push(parse_otp_parameters(parameters));
+        }
+    }
+    Ok(applications)
+}
+
+fn parse_otp_parameters(data: &[u8]) -> Result<GenApp, String> {
+    let mut secret: &[u8] = &[];
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let (mut algorithm, mut digits, mut kind) =
+        (ALGORITHM_UNSPECIFIED, DIGITS_UNSPECIFIED, TYPE_UNSPECIFIED);
+
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(v)) => secret = v,
+            (2, ProtoValue::Bytes(v)) => name = String::from_utf8_lossy(v).into_owned(),
+            (3, ProtoValue::Bytes(v)) => issuer = String::from_utf8_lossy(v).into_owned(),
+            (4, ProtoValue::Varint(v)) => algorithm = v,
+            (5, ProtoValue::Varint(v)) => digits = v,
+            (6, ProtoValue::Varint(v)) => kind = v,
+            _ => {}
+        }
+    }
+
+    if kind != TYPE_TOTP && kind != TYPE_UNSPECIFIED {
+        return Err(format!("'{}' is not a TOTP generator", name));
+    }
+    if algorithm != ALGORITHM_SHA1 && algorithm != ALGORITHM_UNSPECIFIED {
+        return Err(format!("'{}' uses an unsupported algorithm", name));
+    }
+    if digits != DIGITS_SIX && digits != DIGITS_UNSPECIFIED {
+        return Err(format!("'{}' uses an unsupported number of digits", name));
+    }
+
+    // The name is usually the full otpauth label, i.e. "Issuer:account"
+    let account = match name.split_once(':') {
+        Some((prefix, account)) if issuer.is_empty() || prefix == issuer => {
+            if issuer.is_empty() {
+                issuer = String::from(prefix.trim());
+            }
+            account.trim()
+        }
+        _ => name.trim(),
+    };
+    let (name, username) = if issuer.is_empty() {
+        (account, "")
+    } else {
+        (issuer.as_str(), account)
+    };
+    let secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret);
+    GenApp::from_secret(name, username, &secret)
+        .ok_or_else(|| String::from("Couldn't decode secret key"))
+}
+
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Minimal reader of the protobuf wire format, enough for the migration payload
+struct ProtoReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ProtoReader { data, position: 0 }
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'a>)>, String> {
+        if self.position >= self.data.len() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        let value = match key & 0x07 {
+            0 => ProtoValue::Varint(self.read_varint()?),
+            1 => {
+                self.take(8)?;
+                ProtoValue::Fixed
+            }
+            2 => {
+                let length = self.read_varint()? as usize;
+                ProtoValue::Bytes(self.take(length)?)
+            }
+            5 => {
+                self.take(4)?;
+                ProtoValue::Fixed
+            }
+            wire_type => return Err(format!("Unsupported protobuf wire type {}", wire_type)),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(String::from("Malformed protobuf varint"))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| String::from("Truncated migration data"))?;
+        let slice = &self.data[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"123321555";
+    const SECRET_BASE32: &str = "GEZDGMZSGE2TKNI";
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn varint_field(field: u64, value: u64) -> Vec<u8> {
+        [varint(field << 3), varint(value)].concat()
+    }
+
+    fn bytes_field(field: u64, value: &[u8]) -> Vec<u8> {
+        [
+            varint(field << 3 | 2),
+            varint(value.len() as u64),
+            value.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn parameters(name: &str, issuer: &str) -> Vec<u8> {
+        [
+            bytes_field(1, SECRET),
+            bytes_field(2, name.as_bytes()),
+            bytes_field(3, issuer.as_bytes()),
+            varint_field(4, ALGORITHM_SHA1),
+            varint_field(5, DIGITS_SIX),
+            varint_field(6, TYPE_TOTP),
+        ]
+        .concat()
+    }
+
+    fn uri(payload: &[u8]) -> String {
+        let data = base64::engine::general_purpose::STANDARD.encode(payload);
+        let data = data
+            .replace('+', "%2B")
+            .replace('/', "%2F")
+            .replace('=', "%3D");
+        format!("otpauth-migration://offline?data={}", data)
+    }
+
+    #[test]
+    fn parses_every_generator_of_the_payload() {
+        let payload = [
+            bytes_field(1, &parameters("ACME:john", "ACME")),
+            bytes_field(1, &parameters("alice@example.com", "GitHub")),
+            bytes_field(1, &parameters("Bank:bob", "")),
+            bytes_field(1, &parameters("plain", "")),
+            // version, batch size, batch index and batch id
+            varint_field(2, 1),
+            varint_field(3, 1),
+            varint_field(4, 0),
+            varint_field(5, 123_456_789),
+        ]
+        .concat();
+        let apps: Vec<_> = parse_migration_uri(&uri(&payload))
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let fields: Vec<_> = apps
+            .iter()
+            .map(|app| (app.get_name(), app.get_username(), app.get_secret()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("ACME", "john", SECRET_BASE32),
+                ("GitHub", "alice@example.com", SECRET_BASE32),
+                ("Bank", "bob", SECRET_BASE32),
+                ("plain", "", SECRET_BASE32),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let parameters = [
+            parameters("ACME:john", "ACME"),
+            // counter, a fixed64, a fixed32 and an unknown length-delimited field
+            varint_field(7, 42),
+            [varint(9 << 3 | 1), vec![0; 8]].concat(),
+            [varint(10 << 3 | 5), vec![0; 4]].concat(),
+            bytes_field(15, b"future"),
+        ]
+        .concat();
+        let payload = [bytes_field(1, &parameters), bytes_field(20, b"unknown")].concat();
+        let apps = parse_migration_uri(&uri(&payload)).unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].as_ref().unwrap().get_name(), "ACME");
+    }
+
+    #[test]
+    fn defaults_unspecified_parameters() {
+        let parameters = [bytes_field(1, SECRET), bytes_field(2, b"plain")].concat();
+        let apps = parse_migration_uri(&uri(&bytes_field(1, &parameters))).unwrap();
+        assert_eq!(apps[0].as_ref().unwrap().get_name(), "plain");
+    }
+
+    #[test]
+    fn reports_unsupported_generators_on_their_own() {
+        let hotp = [parameters("counter", ""), varint_field(6, 1)].concat();
+        let eight_digits = [parameters("long", ""), varint_field(5, 2)].concat();
+        let sha256 = [parameters("strong", ""), varint_field(4, 2)].concat();
+        let payload = [
+            bytes_field(1, &hotp),
+            bytes_field(1, &eight_digits),
+            bytes_field(1, &sha256),
+            bytes_field(1, &parameters("fine", "")),
+        ]
+        .concat();
+        let apps = parse_migration_uri(&uri(&payload)).unwrap();
+        assert_eq!(
+            apps[0].as_ref().unwrap_err(),
+            "'counter' is not a TOTP generator"
+        );
+        assert_eq!(
+            apps[1].as_ref().unwrap_err(),
+            "'long' uses an unsupported number of digits"
+        );
+        assert_eq!(
+            apps[2].as_ref().unwrap_err(),
+            "'strong' uses an unsupported algorithm"
+        );
+        assert!(apps[3].is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let cases = [
+            // a varint cut short
+            (vec![0x08, 0x80], "Truncated migration data"),
+            // a key cut short
+            (vec![0x80], "Truncated migration data"),
+            // a varint longer than 64 bits
+            (
+                [vec![0x08], vec![0xff; 10]].concat(),
+                "Malformed protobuf varint",
+            ),
+            // bytes shorter than their length
+            (vec![0x0a, 0x05, 0x01], "Truncated migration data"),
+            // a length overflowing the position
+            (
+                [vec![0x0a], varint(u64::MAX)].concat(),
+                "Truncated migration data",
+            ),
+            (vec![0x0b], "Unsupported protobuf wire type 3"),
+        ];
+        for (payload, error) in cases {
+            assert_eq!(
+                parse_migration_uri(&uri(&payload)).unwrap_err(),
+                error,
+                "{:?}",
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn reports_truncated_parameters_on_their_own() {
+        let payload = [
+            bytes_field(1, &[0x12, 0x05, b'a']),
+            bytes_field(1, &parameters("fine", "")),
+        ]
+        .concat();
+        let apps = parse_migration_uri(&uri(&payload)).unwrap();
+        assert_eq!(apps[0].as_ref().unwrap_err(), "Truncated migration data");
+        assert!(apps[1].is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        let cases = [
+            (
+                "otpauth://totp/x?secret=GEZDGMZSGE2TKNIK",
+                "Not an otpauth-migration URI",
+            ),
+            (
+                "otpauth-migration://offline?foo=bar",
+                "Missing migration data",
+            ),
+            (
+                "otpauth-migration://offline?data=%ZZ",
+                "Invalid percent-encoding in '%ZZ'",
+            ),
+        ];
+        for (uri, error) in cases {
+            assert_eq!(parse_migration_uri(uri).unwrap_err(), error);
+        }
+        assert!(parse_migration_uri("otpauth-migration://offline?data=!!!")
+            .unwrap_err()
+            .starts_with("Invalid migration data"));
+    }
+}
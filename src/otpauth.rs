@@ -0,0 +1,231 @@
+use super::GenApp;
+
+const SCHEME: &str = "otpauth://";
+
+impl GenApp {
+    /// Builds a generator from an `otpauth://totp/...` key URI.
+    ///
+    /// The issuer becomes the generator name and the account becomes the username.
+    /// When there is no issuer, the account itself is used as the name.
+    pub fn from_otpauth_uri(uri: &str) -> Result<Self, String> {
+        let uri = uri.trim();
+        match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => {}
+            _ => return Err(String::from("Not an otpauth URI")),
+        }
+        let rest = &uri[SCHEME.len()..];
+        let (kind, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if !kind.eq_ignore_ascii_case("totp") {
+            return Err(format!("Unsupported OTP type '{}'", kind));
+        }
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+        // The separator may be percent-encoded as well, but a literal one
+        // lets the issuer itself contain an encoded colon
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (percent_decode(issuer)?, percent_decode(account)?),
+            None => {
+                let label = percent_decode(label)?;
+                match label.split_once(':') {
+                    Some((issuer, account)) => (String::from(issuer), String::from(account)),
+                    None => (String::new(), label),
+                }
+            }
+        };
+        let (label_issuer, account) = (label_issuer.trim(), account.trim());
+
+        let mut secret = None;
+        let mut issuer = String::from(label_issuer);
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value)?;
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(value),
+                "issuer" => issuer = value,
+                "algorithm" if !value.eq_ignore_ascii_case("SHA1") => {
+                    return Err(format!("Unsupported algorithm '{}'", value))
+                }
+                "digits" if value != "6" => {
+                    return Err(format!("Unsupported number of digits '{}'", value))
+                }
+                "period" if value != "30" => return Err(format!("Unsupported period '{}'", value)),
+                _ => {}
+            }
+        }
+
+        let secret = secret.ok_or_else(|| String::from("Missing secret"))?;
+        let (name, username) = if issuer.is_empty() {
+            (account, "")
+        } else {
+            (issuer.as_str(), account)
+        };
+        if name.is_empty() {
+            return Err(String::from("Missing name"));
+        }
+        GenApp::from_secret(name, username, &secret)
+            .ok_or_else(|| String::from("Couldn't decode secret key"))
+    }
+
+    /// Formats the generator as an `otpauth://totp/...` key URI.
+    ///
+    /// The name is the issuer and the username the account. Without a username the name alone
+    /// is the label, unless it has a colon that would be taken for the separator.
+    pub fn to_otpauth_uri(&self) -> String {
+        if self.username.is_empty() && !self.name.contains(':') {
+            return format!(
+                "{}totp/{}?secret={}",
+                SCHEME,
+                percent_encode(&self.name),
+                self.secret
+            );
+        }
+        format!(
+            "{}totp/{}:{}?secret={}&issuer={}",
+            SCHEME,
+            percent_encode(&self.name),
+            percent_encode(&self.username),
+            self.secret,
+            percent_encode(&self.name)
+        )
+    }
+}
+
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub(crate) fn percent_decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("Invalid percent-encoding in '{}'", input))?;
+                decoded.push(hex);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("Invalid UTF-8 in '{}'", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SECRET;
+
+    #[test]
+    fn parses_issuer_and_account() {
+        let app = GenApp::from_otpauth_uri(
+            "otpauth://totp/ACME%20Co:john.doe%40email.com?secret=GEZDGMZSGE2TKNIK&issuer=ACME%20Co&algorithm=SHA1&digits=6&period=30",
+        )
+        .unwrap();
+        assert_eq!(app.get_name(), "ACME Co");
+        assert_eq!(app.get_username(), "john.doe@email.com");
+        assert_eq!(app.get_secret(), SECRET);
+    }
+
+    #[test]
+    fn prefers_the_issuer_parameter() {
+        let app = GenApp::from_otpauth_uri(
+            "OTPAUTH://TOTP/Old:alice?issuer=New%20%26%20Improved&secret=gezd%20gmzs%20ge2t%20knik",
+        )
+        .unwrap();
+        assert_eq!(app.get_name(), "New & Improved");
+        assert_eq!(app.get_username(), "alice");
+        assert_eq!(app.get_secret(), SECRET);
+    }
+
+    #[test]
+    fn splits_on_an_encoded_separator() {
+        let app =
+            GenApp::from_otpauth_uri("otpauth://totp/ACME%3Ajohn?secret=GEZDGMZSGE2TKNIK").unwrap();
+        assert_eq!(app.get_name(), "ACME");
+        assert_eq!(app.get_username(), "john");
+    }
+
+    #[test]
+    fn uses_the_account_without_issuer() {
+        let app =
+            GenApp::from_otpauth_uri("otpauth://totp/%E2%9C%93%20done?secret=GEZDGMZSGE2TKNIK")
+                .unwrap();
+        assert_eq!(app.get_name(), "✓ done");
+        assert_eq!(app.get_username(), "");
+    }
+
+    #[test]
+    fn rejects_unsupported_uris() {
+        let cases = [
+            ("https://example.com", "Not an otpauth URI"),
+            (
+                "otpauth://hotp/x?secret=GEZDGMZSGE2TKNIK",
+                "Unsupported OTP type 'hotp'",
+            ),
+            ("otpauth://totp/x", "Missing secret"),
+            ("otpauth://totp/?secret=GEZDGMZSGE2TKNIK", "Missing name"),
+            (
+                "otpauth://totp/x?secret=GEZDGMZSGE2TKNIK&digits=8",
+                "Unsupported number of digits '8'",
+            ),
+            (
+                "otpauth://totp/x?secret=GEZDGMZSGE2TKNIK&period=60",
+                "Unsupported period '60'",
+            ),
+            (
+                "otpauth://totp/x?secret=GEZDGMZSGE2TKNIK&algorithm=SHA256",
+                "Unsupported algorithm 'SHA256'",
+            ),
+            ("otpauth://totp/x?secret=1", "Couldn't decode secret key"),
+            (
+                "otpauth://totp/x%2?secret=GEZDGMZSGE2TKNIK",
+                "Invalid percent-encoding in 'x%2'",
+            ),
+            (
+                "otpauth://totp/x%zz?secret=GEZDGMZSGE2TKNIK",
+                "Invalid percent-encoding in 'x%zz'",
+            ),
+            (
+                "otpauth://totp/x%FF?secret=GEZDGMZSGE2TKNIK",
+                "Invalid UTF-8 in 'x%FF'",
+            ),
+        ];
+        for (uri, error) in cases {
+            assert_eq!(GenApp::from_otpauth_uri(uri).unwrap_err(), error, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_uris() {
+        for (name, username) in [
+            ("github", ""),
+            ("host:port", ""),
+            ("Issuer", "account:with:colons"),
+            ("ACME Co", "john.doe@email.com"),
+            ("a:b/c?d&e=f%g", "user+tag@x"),
+            ("Café ☕", "ünïcode"),
+        ] {
+            let app = GenApp::from_secret(name, username, SECRET).unwrap();
+            let uri = app.to_otpauth_uri();
+            let parsed = GenApp::from_otpauth_uri(&uri).unwrap();
+            assert_eq!(parsed.get_name(), name, "{}", uri);
+            assert_eq!(parsed.get_username(), username, "{}", uri);
+            assert_eq!(parsed.get_secret(), SECRET, "{}", uri);
+        }
+    }
+}
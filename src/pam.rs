@@ -0,0 +1,136 @@
+use super::GenApp;
+
+use rand::Rng;
+
+const DEFAULT_OPTIONS: [&str; 4] = [
+    "\" RATE_LIMIT 3 30",
+    "\" WINDOW_SIZE 3",
+    "\" DISALLOW_REUSE",
+    "\" TOTP_AUTH",
+];
+
+impl GenApp {
+    /// Builds a generator from the content of a `~/.google_authenticator` file
+    /// as written by libpam-google-authenticator.
+    ///
+    /// The first line holds the base32 secret, followed by option lines starting with `"`
+    /// and eight-digit scratch codes. Scratch codes are not kept.
+    pub fn from_google_authenticator(
+        name: &str,
+        username: &str,
+        content: &str,
+    ) -> Result<Self, String> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let secret = match lines.next() {
+            Some((_, line)) if !line.starts_with('"') => line,
+            _ => return Err(String::from("Missing secret on the first line")),
+        };
+        for (number, line) in lines {
+            if let Some(option) = line.strip_prefix('"') {
+                let mut words = option.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("HOTP_COUNTER"), _) => {
+                        return Err(String::from("HOTP generators are not supported"))
+                    }
+                    (Some("STEP_SIZE"), Some(step)) if step != "30" => {
+                        return Err(format!("Unsupported step size '{}'", step))
+                    }
+                    _ => {}
+                }
+            } else if line.len() != 8 || !line.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("Unexpected content on line {}", number));
+            }
+        }
+        GenApp::from_secret(name, username, secret)
+            .ok_or_else(|| String::from("Couldn't decode secret key"))
+    }
+
+    /// Formats the generator as a `~/.google_authenticator` file with the default
+    /// options of `google-authenticator` and the given number of fresh scratch codes.
+    pub fn to_google_authenticator(&self, scratch_codes: usize) -> String {
+        let mut lines = vec![base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            &self.secret_bytes,
+        )];
+        lines.extend(DEFAULT_OPTIONS.iter().map(|o| String::from(*o)));
+        let mut rng = rand::thread_rng();
+        lines
+            .extend((0..scratch_codes).map(|_| rng.gen_range(10_000_000..100_000_000).to_string()));
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SECRET;
+
+    #[test]
+    fn parses_a_google_authenticator_file() {
+        let content = format!(
+            "{}\n\" RATE_LIMIT 3 30 1729241112\n\" WINDOW_SIZE 17\n\" DISALLOW_REUSE 57641371\n\" TOTP_AUTH\n\" STEP_SIZE 30\n12345678\n87654321\n\n",
+            SECRET
+        );
+        let app = GenApp::from_google_authenticator("server", "root", &content).unwrap();
+        assert_eq!(app.get_name(), "server");
+        assert_eq!(app.get_username(), "root");
+        assert_eq!(app.get_secret(), SECRET);
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        let cases = [
+            (String::new(), "Missing secret on the first line"),
+            (
+                String::from("\" TOTP_AUTH\n"),
+                "Missing secret on the first line",
+            ),
+            (
+                format!("{}\n\" HOTP_COUNTER 1\n", SECRET),
+                "HOTP generators are not supported",
+            ),
+            (
+                format!("{}\n\" STEP_SIZE 60\n", SECRET),
+                "Unsupported step size '60'",
+            ),
+            (
+                format!("{}\n\n1234567\n", SECRET),
+                "Unexpected content on line 3",
+            ),
+            (
+                format!("{}\nabcdefgh\n", SECRET),
+                "Unexpected content on line 2",
+            ),
+            (String::from("not base32!\n"), "Couldn't decode secret key"),
+        ];
+        for (content, error) in cases {
+            assert_eq!(
+                GenApp::from_google_authenticator("server", "", &content).unwrap_err(),
+                error,
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_with_scratch_codes() {
+        let app = GenApp::from_secret("server", "", SECRET).unwrap();
+        let content = app.to_google_authenticator(5);
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines[0], SECRET);
+        assert_eq!(lines[1..5], DEFAULT_OPTIONS);
+        assert_eq!(lines.len(), 10);
+        assert!(lines[5..]
+            .iter()
+            .all(|code| code.len() == 8 && code.bytes().all(|b| b.is_ascii_digit())));
+
+        let parsed = GenApp::from_google_authenticator("server", "", &content).unwrap();
+        assert_eq!(parsed.get_secret(), SECRET);
+    }
+}
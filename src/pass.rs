@@ -0,0 +1,325 @@
+use super::{GenApp, ImportedEntry};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const GPG_EXTENSION: &str = "gpg";
+const GPG_ID_FILE: &str = ".gpg-id";
+
+/// A password-store directory tree as managed by `pass` and its `pass-otp` extension.
+///
+/// Every `.gpg` file holding an `otpauth://` URI is a generator,
+/// named after its path relative to the store root, e.g. `work/github`.
+pub struct PasswordStore {
+    dir: PathBuf,
+    gpg: String,
+}
+
+impl PasswordStore {
+    pub fn new(dir: PathBuf, gpg: String) -> Self {
+        PasswordStore { dir, gpg }
+    }
+
+    /// Location used by `pass` itself: `$PASSWORD_STORE_DIR` or `~/.password-store`.
+    pub fn default_dir() -> PathBuf {
+        match std::env::var_os("PASSWORD_STORE_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::home_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(".password-store"),
+        }
+    }
+
+    /// Decrypts every entry of the store and parses those containing an `otpauth://` URI.
+    /// Entries without a URI (regular passwords) are skipped.
+    pub fn read_applications(&self) -> Result<Vec<ImportedEntry>, String> {
+        let mut files = vec![];
+        Self::collect_entries(&self.dir, &mut files)
+            .map_err(|e| format!("Couldn't read password store: {}", e))?;
+        files.sort();
+
+        let mut entries = vec![];
+        for file in files {
+            let name = self.entry_name(&file);
+            let content = match self.decrypt(&file) {
+                Ok(content) => content,
+                Err(err) => {
+                    entries.push((name, Err(err)));
+                    continue;
+                }
+            };
+            if let Some(uri) = content
+                .lines()
+                .find(|line| line.trim_start().starts_with("otpauth://"))
+            {
+                let app = GenApp::from_otpauth_uri(uri).map(|mut app| {
+                    app.name = name.clone();
+                    app
+                });
+                entries.push((name, app));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Encrypts the generator's `otpauth://` URI into `<store>/<name>.gpg`
+    /// for the recipients listed in the nearest `.gpg-id` file.
+    pub fn write_application(&self, app: &GenApp) -> Result<(), String> {
+        let relative = Path::new(app.get_name());
+        if app.get_name().is_empty()
+            || relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(format!("Invalid entry name '{}'", app.get_name()));
+        }
+        let file = self
+            .dir
+            .join(format!("{}.{}", app.get_name(), GPG_EXTENSION));
+        if file.exists() {
+            return Err(format!("Entry '{}' already exists", app.get_name()));
+        }
+        let recipients = self.find_recipients(&file)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Couldn't create directory {}: {}", parent.display(), e))?;
+        }
+
+        let mut command = Command::new(&self.gpg);
+        command.args(["--quiet", "--yes", "--batch", "--encrypt", "--output"]);
+        command.arg(&file);
+        for recipient in recipients {
+            command.args(["--recipient", &recipient]);
+        }
+        let content = format!("{}\n", app.to_otpauth_uri());
+        self.run(command, Some(content.as_bytes())).map(|_| ())
+    }
+
+    fn collect_entries(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                Self::collect_entries(&path, files)?;
+            } else if path.extension().is_some_and(|e| e == GPG_EXTENSION) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn entry_name(&self, file: &Path) -> String {
+        let relative = file.strip_prefix(&self.dir).unwrap_or(file);
+        relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn find_recipients(&self, file: &Path) -> Result<Vec<String>, String> {
+        let mut dir = file.parent();
+        while let Some(current) = dir {
+            let gpg_id = current.join(GPG_ID_FILE);
+            if gpg_id.is_file() {
+                let ids = fs::read_to_string(&gpg_id)
+                    .map_err(|e| format!("Couldn't read {}: {}", gpg_id.display(), e))?;
+                return Ok(ids
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(String::from)
+                    .collect());
+            }
+            if current == self.dir {
+                break;
+            }
+            dir = current.parent();
+        }
+        Err(format!(
+            "No {} file found in password store {}",
+            GPG_ID_FILE,
+            self.dir.display()
+        ))
+    }
+
+    fn decrypt(&self, file: &Path) -> Result<String, String> {
+        let mut command = Command::new(&self.gpg);
+        command.args(["--quiet", "--yes", "--batch", "--decrypt"]);
+        command.arg(file);
+        let output = self.run(command, None)?;
+        String::from_utf8(output).map_err(|_| String::from("Decrypted data is not valid UTF-8"))
+    }
+
+    fn run(&self, mut command: Command, input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Couldn't run '{}': {}", self.gpg, e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Some(input) = input {
+                stdin
+                    .write_all(input)
+                    .map_err(|e| format!("Couldn't pass data to '{}': {}", self.gpg, e))?;
+            }
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Couldn't run '{}': {}", self.gpg, e))?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(format!(
+                "'{}' failed: {}",
+                self.gpg,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::SECRET;
+
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    // Stand-in for gpg: "encrypts" by writing the recipients above the content
+    // and "decrypts" by dropping that line, failing for files named `broken.gpg`
+    const STUB_GPG: &str = r#"#!/bin/sh
+mode=""; output=""; recipients=""; file=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --encrypt) mode=encrypt ;;
+        --decrypt) mode=decrypt ;;
+        --output) shift; output="$1" ;;
+        --recipient) shift; recipients="$recipients $1" ;;
+        --*) ;;
+        *) file="$1" ;;
+    esac
+    shift
+done
+if [ "$mode" = encrypt ]; then
+    { echo "recipients:$recipients"; cat; } > "$output"
+elif [ "$(basename "$file")" = broken.gpg ]; then
+    echo "decryption failed: No secret key" >&2
+    exit 2
+else
+    tail -n +2 "$file"
+fi
+"#;
+
+    fn store() -> (TempDir, PasswordStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let gpg = dir.path().join("gpg");
+        fs::write(&gpg, STUB_GPG).unwrap();
+        fs::set_permissions(&gpg, fs::Permissions::from_mode(0o755)).unwrap();
+        let root = dir.path().join("store");
+        fs::create_dir(&root).unwrap();
+        fs::write(
+            root.join(GPG_ID_FILE),
+            "alice@example.com\n# comment\nbob@example.com\n",
+        )
+        .unwrap();
+        let store = PasswordStore::new(root, gpg.to_string_lossy().into_owned());
+        (dir, store)
+    }
+
+    #[test]
+    fn exports_and_imports_generators() {
+        let (_dir, store) = store();
+        let app = GenApp::from_secret("work/github", "alice", SECRET).unwrap();
+        store.write_application(&app).unwrap();
+
+        let written = fs::read_to_string(store.dir.join("work/github.gpg")).unwrap();
+        assert!(written.starts_with("recipients: alice@example.com bob@example.com\n"));
+
+        let entries = store.read_applications().unwrap();
+        assert_eq!(entries.len(), 1);
+        let (name, imported) = &entries[0];
+        assert_eq!(name, "work/github");
+        let imported = imported.as_ref().unwrap();
+        assert_eq!(imported.get_name(), "work/github");
+        assert_eq!(imported.get_username(), "alice");
+        assert_eq!(imported.get_secret(), SECRET);
+    }
+
+    #[test]
+    fn uses_the_nearest_gpg_id() {
+        let (_dir, store) = store();
+        fs::create_dir(store.dir.join("team")).unwrap();
+        fs::write(
+            store.dir.join("team").join(GPG_ID_FILE),
+            "team@example.com\n",
+        )
+        .unwrap();
+        let app = GenApp::from_secret("team/aws", "", SECRET).unwrap();
+        store.write_application(&app).unwrap();
+
+        let written = fs::read_to_string(store.dir.join("team/aws.gpg")).unwrap();
+        assert!(written.starts_with("recipients: team@example.com\n"));
+    }
+
+    #[test]
+    fn skips_entries_without_uri_and_reports_failures() {
+        let (_dir, store) = store();
+        fs::write(store.dir.join("email.gpg"), "recipients: x\nhunter2\n").unwrap();
+        fs::write(store.dir.join("broken.gpg"), "").unwrap();
+        fs::write(
+            store.dir.join("bad.gpg"),
+            "recipients: x\npassword\notpauth://hotp/bad?secret=GEZDGMZSGE2TKNIK\n",
+        )
+        .unwrap();
+
+        let entries = store.read_applications().unwrap();
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["bad", "broken"]);
+        assert_eq!(
+            entries[0].1.as_ref().unwrap_err(),
+            "Unsupported OTP type 'hotp'"
+        );
+        assert!(entries[1].1.as_ref().unwrap_err().contains("No secret key"));
+    }
+
+    #[test]
+    fn refuses_invalid_and_existing_entries() {
+        let (_dir, store) = store();
+        for name in ["../outside", "/etc/passwd", ""] {
+            let mut app = GenApp::from_secret("valid", "", SECRET).unwrap();
+            app.name = String::from(name);
+            assert_eq!(
+                store.write_application(&app).unwrap_err(),
+                format!("Invalid entry name '{}'", name)
+            );
+        }
+        let app = GenApp::from_secret("github", "", SECRET).unwrap();
+        store.write_application(&app).unwrap();
+        assert_eq!(
+            store.write_application(&app).unwrap_err(),
+            "Entry 'github' already exists"
+        );
+    }
+
+    #[test]
+    fn requires_recipients() {
+        let (_dir, store) = store();
+        fs::remove_file(store.dir.join(GPG_ID_FILE)).unwrap();
+        let app = GenApp::from_secret("github", "", SECRET).unwrap();
+        assert!(store
+            .write_application(&app)
+            .unwrap_err()
+            .starts_with("No .gpg-id file found"));
+    }
+}
@@ -0,0 +1,170 @@
+use super::atomic;
+use super::{sidecar_path, DatabaseError};
+
+use age::secrecy::ExposeSecret;
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Recovery key of a password-protected database.
+///
+/// The key is an age identity shown to the user once. The password of the database is kept
+/// encrypted to it next to the database in a `.recovery` file, which starts with a line
+/// holding the public part of the key, so the file can be updated when the password changes.
+pub struct Recovery {
+    path: PathBuf,
+}
+
+impl Recovery {
+    pub fn for_vault(vault: &Path) -> Self {
+        Recovery {
+            path: sidecar_path(vault, "recovery"),
+        }
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Generates a new recovery key for the password and returns it
+    pub fn create(&self, secret: &str) -> Result<String, DatabaseError> {
+        let identity = age::x25519::Identity::generate();
+        self.write(&identity.to_public(), secret)?;
+        Ok(identity.to_string().expose_secret().clone())
+    }
+
+    /// Keeps the existing recovery key working after the password was changed
+    pub fn update(&self, secret: &str) -> Result<(), DatabaseError> {
+        let (recipient, _) = self.read()?;
+        self.write(&recipient, secret)
+    }
+
+    /// Removes the recovery key, e.g. once the database isn't protected by a password anymore
+    pub fn remove(&self) -> Result<(), DatabaseError> {
+        match std::fs::remove_file(&self.path) {
+            Ok(_) => Ok(()),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Returns the password of the database
+    pub fn recover_secret(&self, key: &str) -> Result<String, DatabaseError> {
+        let identity = age::x25519::Identity::from_str(key.trim())
+            .map_err(|_| DatabaseError::WrongRecoveryKey)?;
+        let (_, data) = self.read()?;
+        let decryptor = match age::Decryptor::new(data.as_slice()) {
+            Ok(age::Decryptor::Recipients(d)) => d,
+            _ => return Err(DatabaseError::Corrupt),
+        };
+
+        let mut decrypted = vec![];
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .map_err(|err| match err {
+                age::DecryptError::NoMatchingKeys => DatabaseError::WrongRecoveryKey,
+                _ => DatabaseError::Corrupt,
+            })?;
+        reader
+            .read_to_end(&mut decrypted)
+            .map_err(|_| DatabaseError::Corrupt)?;
+
+        String::from_utf8(decrypted).map_err(|_| DatabaseError::Corrupt)
+    }
+
+    fn read(&self) -> Result<(age::x25519::Recipient, Vec<u8>), DatabaseError> {
+        let data = std::fs::read(&self.path)?;
+        let (recipient, encrypted) = data
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|i| (&data[..i], &data[i + 1..]))
+            .ok_or(DatabaseError::Corrupt)?;
+        let recipient = std::str::from_utf8(recipient)
+            .ok()
+            .and_then(|r| age::x25519::Recipient::from_str(r).ok())
+            .ok_or(DatabaseError::Corrupt)?;
+        Ok((recipient, encrypted.to_vec()))
+    }
+
+    fn write(&self, recipient: &age::x25519::Recipient, secret: &str) -> Result<(), DatabaseError> {
+        let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient.clone())])
+            .ok_or_else(|| DatabaseError::Encryption(String::from("No recipients")))?;
+
+        let mut data = format!("{}\n", recipient).into_bytes();
+        let mut writer = encryptor
+            .wrap_output(&mut data)
+            .map_err(|err| DatabaseError::Encryption(err.to_string()))?;
+        writer.write_all(secret.as_bytes())?;
+        writer.finish()?;
+
+        atomic::write_file(&self.path, &data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovery = Recovery::for_vault(&dir.path().join("totp.safe"));
+        assert!(!recovery.exists());
+        let key = recovery.create("password").unwrap();
+        assert!(recovery.exists());
+        assert_eq!(recovery.get_path(), &dir.path().join("totp.safe.recovery"));
+        assert_eq!(recovery.recover_secret(&key).unwrap(), "password");
+        assert_eq!(
+            recovery.recover_secret(&format!(" {}\n", key)).unwrap(),
+            "password"
+        );
+    }
+
+    #[test]
+    fn keeps_the_key_when_the_password_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovery = Recovery::for_vault(&dir.path().join("totp.safe"));
+        let key = recovery.create("password").unwrap();
+        recovery.update("new password").unwrap();
+        assert_eq!(recovery.recover_secret(&key).unwrap(), "new password");
+    }
+
+    #[test]
+    fn refuses_wrong_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovery = Recovery::for_vault(&dir.path().join("totp.safe"));
+        recovery.create("password").unwrap();
+        let other_key = age::x25519::Identity::generate().to_string();
+        for key in [other_key.expose_secret().as_str(), "not a key", ""] {
+            assert!(matches!(
+                recovery.recover_secret(key),
+                Err(DatabaseError::WrongRecoveryKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn reports_damaged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovery = Recovery::for_vault(&dir.path().join("totp.safe"));
+        let key = recovery.create("password").unwrap();
+        std::fs::write(recovery.get_path(), "no public key").unwrap();
+        assert!(matches!(
+            recovery.recover_secret(&key),
+            Err(DatabaseError::Corrupt)
+        ));
+        assert!(matches!(
+            recovery.update("password"),
+            Err(DatabaseError::Corrupt)
+        ));
+        recovery.remove().unwrap();
+        assert!(!recovery.exists());
+        recovery.remove().unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use super::{DatabaseError, GenApp};
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+/// Version of the content written to databases.
+///
+/// Bump it along with a new migration in [`MIGRATIONS`] whenever the content changes shape.
+/// Fields added to `GenApp` need a `#[serde(default)]`, so that files written before
+/// the field existed still parse.
+pub(crate) const DATABASE_VERSION: u8 = 1;
+
+type Migration = fn(&mut Value) -> Result<(), DatabaseError>;
+
+// Migrations of the content, the first one upgrades version 1 to version 2 and so on
+const MIGRATIONS: &[Migration] = &[];
+
+const _: () = assert!(MIGRATIONS.len() + 1 == DATABASE_VERSION as usize);
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonDatabaseSchema {
+    pub(crate) version: u8,
+    pub(crate) content: DatabaseContentSchema,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DatabaseContentSchema {
+    pub(crate) applications: HashMap<String, GenApp>,
+}
+
+impl JsonDatabaseSchema {
+    pub(crate) fn new(applications: HashMap<String, GenApp>) -> Self {
+        JsonDatabaseSchema {
+            version: DATABASE_VERSION,
+            content: DatabaseContentSchema { applications },
+        }
+    }
+
+    /// Parses the decrypted content of a database, upgrading it from older versions.
+    /// The upgraded content is written on the next save.
+    pub(crate) fn parse(data: &str) -> Result<Self, DatabaseError> {
+        let mut content: Value = serde_json::from_str(data).map_err(|_| DatabaseError::Corrupt)?;
+        migrate(&mut content, MIGRATIONS)?;
+        serde_json::from_value(content).map_err(|_| DatabaseError::Corrupt)
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String, DatabaseError> {
+        Ok(serde_json::to_string(self).map_err(std::io::Error::from)?)
+    }
+}
+
+/// Upgrades the content to the version following the last of `migrations`
+fn migrate(content: &mut Value, migrations: &[Migration]) -> Result<(), DatabaseError> {
+    let latest = migrations.len() as u64 + 1;
+    let version = content
+        .get("version")
+        .and_then(Value::as_u64)
+        .filter(|version| *version > 0)
+        .ok_or(DatabaseError::Corrupt)?;
+    if version > latest {
+        return Err(DatabaseError::UnsupportedVersion);
+    }
+    for migration in &migrations[version as usize - 1..] {
+        migration(content)?;
+    }
+    content["version"] = Value::from(latest);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{applications, SECRET};
+
+    use serde_json::json;
+
+    fn version_1(secret: &str) -> String {
+        json!({
+            "version": 1,
+            "content": {"applications": {"demo": {
+                "name": "demo",
+                "secret": secret,
+                "username": "alice",
+                "secret_bytes": [49, 50, 51, 51, 50, 49, 53, 53, 53, 10],
+            }}}
+        })
+        .to_string()
+    }
+
+    // Version 2 of a test chain: the username was stored as `login`
+    fn rename_login(content: &mut Value) -> Result<(), DatabaseError> {
+        let applications = content
+            .pointer_mut("/content/applications")
+            .and_then(Value::as_object_mut)
+            .ok_or(DatabaseError::Corrupt)?;
+        for app in applications.values_mut() {
+            let app = app.as_object_mut().ok_or(DatabaseError::Corrupt)?;
+            let login = app.remove("login").ok_or(DatabaseError::Corrupt)?;
+            app.insert(String::from("username"), login);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_older_versions() {
+        let mut content: Value =
+            serde_json::from_str(&version_1(SECRET).replace("username", "login")).unwrap();
+        migrate(&mut content, &[rename_login]).unwrap();
+        let schema: JsonDatabaseSchema = serde_json::from_value(content.clone()).unwrap();
+        assert_eq!(schema.version, 2);
+        assert_eq!(schema.content.applications["demo"].get_username(), "alice");
+
+        // Content at the latest version is left as is
+        migrate(&mut content, &[rename_login]).unwrap();
+        assert_eq!(content["version"], 2);
+    }
+
+    #[test]
+    fn keeps_the_current_version_as_is() {
+        let json = JsonDatabaseSchema::new(applications(&["demo"]))
+            .to_json()
+            .unwrap();
+        let schema = JsonDatabaseSchema::parse(&json).unwrap();
+        assert_eq!(schema.version, DATABASE_VERSION);
+        assert_eq!(schema.content.applications["demo"].get_secret(), SECRET);
+    }
+
+    #[test]
+    fn fills_in_missing_fields() {
+        let json = json!({
+            "version": DATABASE_VERSION,
+            "content": {"applications": {"demo": {
+                "name": "demo",
+                "secret": SECRET,
+                "secret_bytes": [49, 50, 51, 51, 50, 49, 53, 53, 53, 10],
+            }}}
+        });
+        let schema = JsonDatabaseSchema::parse(&json.to_string()).unwrap();
+        assert_eq!(schema.content.applications["demo"].get_username(), "");
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let json = json!({"version": DATABASE_VERSION + 1, "content": {"whatever": true}});
+        assert!(matches!(
+            JsonDatabaseSchema::parse(&json.to_string()),
+            Err(DatabaseError::UnsupportedVersion)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_content() {
+        for json in [
+            String::from("not json"),
+            json!({"content": {"applications": {}}}).to_string(),
+            json!({"version": 0, "content": {"applications": {}}}).to_string(),
+            json!({"version": "1", "content": {"applications": {}}}).to_string(),
+            json!({"version": 1, "content": {}}).to_string(),
+            version_1("").replace("\"secret\":\"\"", "\"secret\":1"),
+        ] {
+            assert!(
+                matches!(
+                    JsonDatabaseSchema::parse(&json),
+                    Err(DatabaseError::Corrupt)
+                ),
+                "{}",
+                json
+            );
+        }
+    }
+}
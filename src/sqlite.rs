@@ -0,0 +1,683 @@
+use super::{
+    Database, DatabaseError, GenApp, JsonDatabase, KDF_PARAMS_SIZE, KEY_SIZE, NONCE_SIZE, TAG_SIZE,
+};
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes;
+use crypto::aes_gcm::AesGcm;
+use crypto::util::fixed_time_eq;
+use rand::RngCore;
+use rusqlite::{
+    params, Connection, ErrorCode, OpenFlags, OptionalExtension, Transaction, TransactionBehavior,
+};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Version of the tables, stored as the `user_version` of the database.
+/// A database at version 0 hasn't been saved yet and has no tables.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE settings (
+        name TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE applications (
+        name TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        secret BLOB NOT NULL
+    );
+";
+
+/// Database stored in SQLite, one row per application.
+///
+/// Names and usernames are stored as is, secrets are encrypted with AES-256-GCM
+/// with a key derived by scrypt from the password, whose parameters are kept in the `settings` table.
+/// Saving only writes the rows that changed, and refuses to overwrite rows
+/// changed by another process since they were loaded.
+pub struct SqliteDatabase {
+    file_path: PathBuf,
+    secret: String,
+    // Key derived from the password, along with the scrypt parameters it was derived with
+    key: RefCell<Option<(Vec<u8>, [u8; KEY_SIZE])>>,
+    // Set when the password changes, so that every secret is encrypted again on the next save
+    rekey: Cell<bool>,
+    // Rows as they were last read or written, `None` before that
+    loaded_rows: RefCell<Option<HashMap<String, Row>>>,
+}
+
+/// An application as stored in a row: its username and encrypted secret
+type Row = (String, Vec<u8>);
+
+impl SqliteDatabase {
+    pub fn new(path: PathBuf, secret: String) -> Self {
+        SqliteDatabase {
+            file_path: path,
+            secret,
+            key: RefCell::new(None),
+            rekey: Cell::new(false),
+            loaded_rows: RefCell::new(None),
+        }
+    }
+
+    pub fn get_file_path(&self) -> &PathBuf {
+        &self.file_path
+    }
+
+    /// Opens the file, creating it unless it's only read
+    fn open(&self, read_only: bool) -> Result<Connection, DatabaseError> {
+        let connection = if read_only {
+            Connection::open_with_flags(&self.file_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?
+        } else {
+            Connection::open(&self.file_path)?
+        };
+        connection.busy_timeout(Duration::from_secs(10))?;
+        Ok(connection)
+    }
+
+    /// Returns the key of the database, checking the password against the stored key check.
+    /// Returns `None` for a database that hasn't been saved yet.
+    fn key(&self, tx: &Transaction) -> Result<Option<[u8; KEY_SIZE]>, DatabaseError> {
+        if check_version(tx)? == 0 {
+            return Ok(None);
+        }
+        let kdf_params = get_setting(tx, "kdf")?
+            .filter(|params| params.len() == KDF_PARAMS_SIZE)
+            .ok_or(DatabaseError::Corrupt)?;
+        let key_check = get_setting(tx, "key_check")?.ok_or(DatabaseError::Corrupt)?;
+        if let Some((params, key)) = self.key.borrow().as_ref() {
+            if *params == kdf_params {
+                return Ok(Some(*key));
+            }
+        }
+        let key = JsonDatabase::derive_secret_key(&self.secret, &kdf_params)?;
+        if !fixed_time_eq(&key_check, &JsonDatabase::key_check(&key)) {
+            return Err(DatabaseError::WrongPassword);
+        }
+        self.key.replace(Some((kdf_params, key)));
+        Ok(Some(key))
+    }
+
+    /// Derives a key from the password with new scrypt parameters and stores them.
+    /// The tables must exist already.
+    fn new_key(&self, tx: &Transaction) -> Result<[u8; KEY_SIZE], DatabaseError> {
+        let kdf_params = JsonDatabase::new_kdf_params();
+        let key = JsonDatabase::derive_secret_key(&self.secret, &kdf_params)?;
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        set_setting(tx, "kdf", &kdf_params)?;
+        set_setting(tx, "key_check", &JsonDatabase::key_check(&key))?;
+        self.key.replace(Some((kdf_params, key)));
+        Ok(key)
+    }
+
+    /// Changes the rows of the named applications in a transaction, creating the tables of a new database.
+    /// Fails if another process changed any of these rows since they were loaded.
+    /// Every application is saved instead when the password changed.
+    fn change_rows<F>(
+        &self,
+        applications: &HashMap<String, GenApp>,
+        names: &[&str],
+        change: F,
+    ) -> Result<(), DatabaseError>
+    where
+        F: FnOnce(&Transaction, &[u8; KEY_SIZE]) -> Result<(), DatabaseError>,
+    {
+        if self.rekey.get() {
+            return self.save_applications(applications);
+        }
+        let mut connection = self.open(false)?;
+        let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let key = match self.key(&tx)? {
+            Some(key) => key,
+            None => {
+                tx.execute_batch(SCHEMA)?;
+                self.new_key(&tx)?
+            }
+        };
+        // The rows must be as they were loaded, absent for a new name
+        if let Some(loaded) = self.loaded_rows.borrow().as_ref() {
+            for name in names {
+                if read_row(&tx, name)?.as_ref() != loaded.get(*name) {
+                    return Err(DatabaseError::ConcurrentModification);
+                }
+            }
+        }
+        change(&tx, &key)?;
+        let rows = names
+            .iter()
+            .map(|name| Ok((*name, read_row(&tx, name)?)))
+            .collect::<Result<Vec<_>, DatabaseError>>()?;
+        tx.commit()?;
+        if let Some(loaded) = self.loaded_rows.borrow_mut().as_mut() {
+            for (name, row) in rows {
+                match row {
+                    Some(row) => loaded.insert(String::from(name), row),
+                    None => loaded.remove(name),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn read_rows(tx: &Transaction) -> Result<HashMap<String, Row>, DatabaseError> {
+        let mut statement = tx.prepare("SELECT name, username, secret FROM applications")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError> {
+        if !self.file_path.exists() {
+            self.loaded_rows.replace(Some(HashMap::new()));
+            return Ok(HashMap::new());
+        }
+        let mut connection = self.open(true)?;
+        let tx = connection.transaction()?;
+        let key = match self.key(&tx)? {
+            Some(key) => key,
+            None => {
+                self.loaded_rows.replace(Some(HashMap::new()));
+                return Ok(HashMap::new());
+            }
+        };
+        let rows = Self::read_rows(&tx)?;
+        let applications = rows
+            .iter()
+            .map(|(name, (username, secret))| {
+                let secret = decrypt_secret(&key, name, secret)?;
+                let app =
+                    GenApp::from_secret(name, username, &secret).ok_or(DatabaseError::Corrupt)?;
+                Ok((name.clone(), app))
+            })
+            .collect::<Result<_, DatabaseError>>()?;
+        self.loaded_rows.replace(Some(rows));
+        Ok(applications)
+    }
+
+    fn save_applications(
+        &self,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        let mut connection = self.open(false)?;
+        let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let new = check_version(&tx)? == 0;
+        if new {
+            tx.execute_batch(SCHEMA)?;
+        }
+        // The stored key check is of the previous password when it was changed
+        let key = if self.rekey.get() || new {
+            None
+        } else {
+            self.key(&tx)?
+        };
+        let stored = Self::read_rows(&tx)?;
+        // Rows that aren't in memory were removed here, unless another process added them
+        let loaded = self.loaded_rows.borrow();
+        if loaded.as_ref().is_some_and(|loaded| *loaded != stored) {
+            return Err(DatabaseError::ConcurrentModification);
+        }
+        drop(loaded);
+        let key = match key {
+            Some(key) => key,
+            None => self.new_key(&tx)?,
+        };
+
+        for name in stored
+            .keys()
+            .filter(|name| !applications.contains_key(*name))
+        {
+            tx.execute("DELETE FROM applications WHERE name = ?1", params![name])?;
+        }
+        for (name, app) in applications {
+            let unchanged = !self.rekey.get()
+                && stored.get(name).is_some_and(|(username, secret)| {
+                    *username == app.username
+                        && decrypt_secret(&key, name, secret).is_ok_and(|s| s == app.secret)
+                });
+            if unchanged {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO applications (name, username, secret) VALUES (?1, ?2, ?3)
+                ON CONFLICT (name) DO UPDATE SET username = ?2, secret = ?3",
+                params![name, app.username, encrypt_secret(&key, name, &app.secret)],
+            )?;
+        }
+        let rows = Self::read_rows(&tx)?;
+        tx.commit()?;
+        self.loaded_rows.replace(Some(rows));
+        self.rekey.set(false);
+        Ok(())
+    }
+
+    fn set_secret(&mut self, secret: String) -> Result<(), DatabaseError> {
+        self.secret = secret;
+        self.rekey.set(true);
+        Ok(())
+    }
+
+    fn supports_row_updates(&self) -> bool {
+        true
+    }
+
+    /// Fails if another process saved an application with the same name
+    fn insert_application(
+        &self,
+        app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.change_rows(applications, &[&app.name], |tx, key| {
+            let inserted = tx.execute(
+                "INSERT INTO applications (name, username, secret) VALUES (?1, ?2, ?3)
+                ON CONFLICT (name) DO NOTHING",
+                params![
+                    app.name,
+                    app.username,
+                    encrypt_secret(key, &app.name, &app.secret)
+                ],
+            )?;
+            match inserted {
+                0 => Err(DatabaseError::ConcurrentModification),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Fails if another process removed the application or saved one with its new name
+    fn update_application(
+        &self,
+        name: &str,
+        app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.change_rows(applications, &[name, &app.name], |tx, key| {
+            if app.name != name && read_row(tx, &app.name)?.is_some() {
+                return Err(DatabaseError::ConcurrentModification);
+            }
+            let updated = tx.execute(
+                "UPDATE applications SET name = ?2, username = ?3, secret = ?4 WHERE name = ?1",
+                params![
+                    name,
+                    app.name,
+                    app.username,
+                    encrypt_secret(key, &app.name, &app.secret)
+                ],
+            )?;
+            match updated {
+                0 => Err(DatabaseError::ConcurrentModification),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Fails if another process removed the application already
+    fn remove_application(
+        &self,
+        name: &str,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        self.change_rows(applications, &[name], |tx, _| {
+            match tx.execute("DELETE FROM applications WHERE name = ?1", params![name])? {
+                0 => Err(DatabaseError::ConcurrentModification),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err.sqlite_error_code() {
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => DatabaseError::Corrupt,
+            // Another process still holds the lock after the busy timeout
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+                DatabaseError::ConcurrentModification
+            }
+            _ => DatabaseError::Io(std::io::Error::other(err)),
+        }
+    }
+}
+
+/// Returns the version of the tables, 0 for a database that hasn't been saved yet
+fn check_version(tx: &Transaction) -> Result<i64, DatabaseError> {
+    let version: i64 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(DatabaseError::UnsupportedVersion);
+    }
+    Ok(version)
+}
+
+fn read_row(tx: &Transaction, name: &str) -> Result<Option<Row>, DatabaseError> {
+    Ok(tx
+        .query_row(
+            "SELECT username, secret FROM applications WHERE name = ?1",
+            params![name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?)
+}
+
+fn get_setting(tx: &Transaction, name: &str) -> Result<Option<Vec<u8>>, DatabaseError> {
+    Ok(tx
+        .query_row(
+            "SELECT value FROM settings WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn set_setting(tx: &Transaction, name: &str, value: &[u8]) -> Result<(), DatabaseError> {
+    tx.execute(
+        "INSERT INTO settings (name, value) VALUES (?1, ?2)
+        ON CONFLICT (name) DO UPDATE SET value = ?2",
+        params![name, value],
+    )?;
+    Ok(())
+}
+
+// The name of the application is authenticated along with the secret,
+// so that secrets can't be swapped between rows
+fn encrypt_secret(key: &[u8], name: &str, secret: &str) -> Vec<u8> {
+    let mut nonce = [0; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let mut encrypted = vec![0; secret.len()];
+    let mut tag = [0; TAG_SIZE];
+    AesGcm::new(aes::KeySize::KeySize256, key, &nonce, name.as_bytes()).encrypt(
+        secret.as_bytes(),
+        &mut encrypted,
+        &mut tag,
+    );
+    [&nonce[..], &encrypted, &tag].concat()
+}
+
+fn decrypt_secret(key: &[u8], name: &str, data: &[u8]) -> Result<String, DatabaseError> {
+    if data.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(DatabaseError::Corrupt);
+    }
+    let (nonce, rest) = data.split_at(NONCE_SIZE);
+    let (encrypted, tag) = rest.split_at(rest.len() - TAG_SIZE);
+    let mut decrypted = vec![0; encrypted.len()];
+    if !AesGcm::new(aes::KeySize::KeySize256, key, nonce, name.as_bytes()).decrypt(
+        encrypted,
+        &mut decrypted,
+        tag,
+    ) {
+        return Err(DatabaseError::Corrupt);
+    }
+    String::from_utf8(decrypted).map_err(|_| DatabaseError::Corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{applications, SECRET};
+    use crate::RusTOTPony;
+
+    use tempfile::TempDir;
+
+    fn database(dir: &TempDir) -> SqliteDatabase {
+        SqliteDatabase::new(dir.path().join("totp.safe"), String::from("password"))
+    }
+
+    fn saved_names(dir: &TempDir) -> Vec<String> {
+        let mut names: Vec<_> = database(dir)
+            .get_applications()
+            .unwrap()
+            .into_keys()
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn saves_and_loads_applications() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(&dir);
+        assert!(db.get_applications().unwrap().is_empty());
+        db.save_applications(&applications(&["github", "gitlab"]))
+            .unwrap();
+        let loaded = database(&dir).get_applications().unwrap();
+        assert_eq!(loaded["github"].get_secret(), SECRET);
+        assert_eq!(saved_names(&dir), ["github", "gitlab"]);
+
+        let wrong = SqliteDatabase::new(dir.path().join("totp.safe"), String::from("wrong"));
+        assert!(matches!(
+            wrong.get_applications(),
+            Err(DatabaseError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn creates_the_tables_when_saving() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        std::fs::write(&path, b"").unwrap();
+        assert!(database(&dir).get_applications().unwrap().is_empty());
+        assert!(std::fs::read(&path).unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        let db = database(&dir);
+        let apps = applications(&["github"]);
+        db.insert_application(&apps["github"], &apps).unwrap();
+        assert_eq!(saved_names(&dir), ["github"]);
+    }
+
+    #[test]
+    fn reports_damaged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        std::fs::write(&path, [b'x'; 4096]).unwrap();
+        assert!(matches!(
+            database(&dir).get_applications(),
+            Err(DatabaseError::Corrupt)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        database(&dir)
+            .save_applications(&applications(&["github"]))
+            .unwrap();
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute("DELETE FROM settings WHERE name = 'kdf'", [])
+            .unwrap();
+        assert!(matches!(
+            database(&dir).get_applications(),
+            Err(DatabaseError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn maps_sqlite_errors() {
+        let failure = |code| rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None);
+        assert!(matches!(
+            DatabaseError::from(failure(rusqlite::ffi::SQLITE_BUSY)),
+            DatabaseError::ConcurrentModification
+        ));
+        assert!(matches!(
+            DatabaseError::from(failure(rusqlite::ffi::SQLITE_NOTADB)),
+            DatabaseError::Corrupt
+        ));
+        assert!(matches!(
+            DatabaseError::from(failure(rusqlite::ffi::SQLITE_FULL)),
+            DatabaseError::Io(_)
+        ));
+    }
+
+    #[test]
+    fn keeps_unchanged_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(&dir);
+        db.get_applications().unwrap();
+        db.save_applications(&applications(&["github", "gitlab"]))
+            .unwrap();
+        let before = db.loaded_rows.borrow().clone().unwrap();
+        db.save_applications(&applications(&["github"])).unwrap();
+        let after = db.loaded_rows.borrow().clone().unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after["github"], before["github"]);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_concurrent_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        database(&dir)
+            .save_applications(&applications(&["github"]))
+            .unwrap();
+        let first = database(&dir);
+        let mut apps = first.get_applications().unwrap();
+        let second = database(&dir);
+        second.get_applications().unwrap();
+        second
+            .save_applications(&applications(&["github", "gitlab"]))
+            .unwrap();
+
+        apps.extend(applications(&["bitbucket"]));
+        assert!(matches!(
+            first.save_applications(&apps),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+        assert_eq!(saved_names(&dir), ["github", "gitlab"]);
+    }
+
+    #[test]
+    fn changes_single_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(&dir);
+        db.get_applications().unwrap();
+        let mut apps = applications(&["github"]);
+        db.save_applications(&apps).unwrap();
+
+        let gitlab = GenApp::from_secret("gitlab", "", SECRET).unwrap();
+        apps.insert(String::from("gitlab"), gitlab.clone());
+        db.insert_application(&gitlab, &apps).unwrap();
+        let mut renamed = apps.remove("github").unwrap();
+        renamed.name = String::from("work/github");
+        apps.insert(renamed.name.clone(), renamed.clone());
+        db.update_application("github", &renamed, &apps).unwrap();
+        assert_eq!(saved_names(&dir), ["gitlab", "work/github"]);
+
+        apps.remove("gitlab");
+        db.remove_application("gitlab", &apps).unwrap();
+        assert_eq!(saved_names(&dir), ["work/github"]);
+        // The rows written here aren't taken for changes of another process
+        db.save_applications(&apps).unwrap();
+    }
+
+    #[test]
+    fn saves_several_changes_to_a_new_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = RusTOTPony::new(database(&dir)).unwrap();
+        app.create_application("github", "", SECRET).unwrap();
+        app.create_application("gitlab", "", SECRET).unwrap();
+        app.rename_application("github", "work/github").unwrap();
+        app.delete_application("gitlab").unwrap();
+        app.flush().unwrap();
+        assert_eq!(saved_names(&dir), ["work/github"]);
+    }
+
+    #[test]
+    fn refuses_to_flush_over_changes_of_another_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        database(&dir)
+            .save_applications(&applications(&["github"]))
+            .unwrap();
+        let mut stale = RusTOTPony::new(database(&dir)).unwrap();
+        let mut other = RusTOTPony::new(database(&dir)).unwrap();
+        other.delete_application("github").unwrap();
+        other
+            .create_application("github", "bob", "MFRGGZDFMZTWQ2LK")
+            .unwrap();
+        other.flush().unwrap();
+
+        stale.rename_application("github", "gh").unwrap();
+        assert!(matches!(
+            stale.flush(),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+        let stored = database(&dir).get_applications().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored["github"].username, "bob");
+    }
+
+    #[test]
+    fn refuses_conflicting_row_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(&dir);
+        db.get_applications().unwrap();
+        let apps = applications(&["github", "gitlab"]);
+        db.save_applications(&apps).unwrap();
+
+        let other = database(&dir);
+        other.get_applications().unwrap();
+        other.save_applications(&applications(&["github"])).unwrap();
+
+        let gitlab = &apps["gitlab"];
+        let github = &apps["github"];
+        for result in [
+            db.insert_application(github, &apps),
+            db.update_application("gitlab", gitlab, &apps),
+            db.remove_application("gitlab", &apps),
+            db.update_application("bitbucket", github, &apps),
+        ] {
+            assert!(matches!(result, Err(DatabaseError::ConcurrentModification)));
+        }
+    }
+
+    #[test]
+    fn refuses_to_change_rows_replaced_by_another_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(&dir);
+        db.get_applications().unwrap();
+        let apps = applications(&["github"]);
+        db.save_applications(&apps).unwrap();
+
+        let other = database(&dir);
+        other.get_applications().unwrap();
+        let replaced = GenApp::from_secret("github", "bob", "MFRGGZDFMZTWQ2LK").unwrap();
+        other
+            .save_applications(&HashMap::from([(String::from("github"), replaced)]))
+            .unwrap();
+
+        let mut renamed = apps["github"].clone();
+        renamed.name = String::from("gh");
+        assert!(matches!(
+            db.update_application("github", &renamed, &apps),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+        assert!(matches!(
+            db.remove_application("github", &apps),
+            Err(DatabaseError::ConcurrentModification)
+        ));
+        let stored = database(&dir).get_applications().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored["github"].username, "bob");
+        assert_eq!(stored["github"].get_secret(), "MFRGGZDFMZTWQ2LK");
+    }
+
+    #[test]
+    fn changes_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = database(&dir);
+        let apps = applications(&["github"]);
+        db.save_applications(&apps).unwrap();
+        db.get_applications().unwrap();
+        db.set_secret(String::from("new password")).unwrap();
+        db.save_applications(&apps).unwrap();
+
+        let reopened =
+            SqliteDatabase::new(dir.path().join("totp.safe"), String::from("new password"));
+        assert_eq!(
+            reopened.get_applications().unwrap()["github"].get_secret(),
+            SECRET
+        );
+        assert!(matches!(
+            database(&dir).get_applications(),
+            Err(DatabaseError::WrongPassword)
+        ));
+    }
+}
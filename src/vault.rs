@@ -0,0 +1,242 @@
+#[cfg(feature = "sqlite")]
+use super::SqliteDatabase;
+use super::{
+    AgeJsonDatabase, Database, DatabaseError, GenApp, JsonDatabase, JsonDatabaseTrait, IV_SIZE,
+};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of age-encrypted files
+pub(crate) const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+/// Magic bytes at the start of AES-encrypted files, followed by a format version byte
+pub(crate) const AES_MAGIC: &[u8] = b"RusTOTPony\0";
+/// Magic bytes at the start of SQLite files
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+// Smallest AES-CBC payload: an IV and the padded blocks of an empty database,
+// `{"version":1,"content":{"applications":{}}}`
+const MIN_AES_SIZE: usize = IV_SIZE + 48;
+
+/// Encryption formats of database files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultFormat {
+    /// Encrypted with age and a passphrase
+    Age,
+    /// Encrypted with age to public keys, see [`AgeIdentityDatabase`](super::AgeIdentityDatabase)
+    AgeRecipients,
+    /// Encrypted with AES by [`JsonDatabase`], with or without the RusTOTPony header
+    Aes,
+    /// Stored in SQLite with encrypted secrets by `SqliteDatabase`, see the `sqlite` feature
+    Sqlite,
+}
+
+impl fmt::Display for VaultFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VaultFormat::Age => f.write_str("an age passphrase"),
+            VaultFormat::AgeRecipients => f.write_str("age public keys"),
+            VaultFormat::Aes => f.write_str("AES"),
+            VaultFormat::Sqlite => f.write_str("a password in SQLite"),
+        }
+    }
+}
+
+impl VaultFormat {
+    /// Recognises the format of encrypted database content
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(AGE_MAGIC) {
+            // Passphrase-encrypted files have a single scrypt stanza in the header
+            let passphrase = data
+                .split(|b| *b == b'\n')
+                .take_while(|line| !line.starts_with(b"---"))
+                .any(|line| line.starts_with(b"-> scrypt "));
+            Some(if passphrase {
+                VaultFormat::Age
+            } else {
+                VaultFormat::AgeRecipients
+            })
+        } else if data.starts_with(AES_MAGIC) {
+            Some(VaultFormat::Aes)
+        } else if data.starts_with(SQLITE_MAGIC) {
+            Some(VaultFormat::Sqlite)
+        } else if data.len() >= MIN_AES_SIZE && (data.len() - IV_SIZE).is_multiple_of(16) {
+            // Files written before the header was introduced are just an IV and AES blocks
+            Some(VaultFormat::Aes)
+        } else {
+            None
+        }
+    }
+
+    /// Recognises the format of a database file. Returns `None` if the file doesn't exist.
+    pub fn detect_file(path: &Path) -> Result<Option<Self>, DatabaseError> {
+        let data = match std::fs::read(path) {
+            Ok(d) => d,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        match Self::detect(&data) {
+            Some(format) => Ok(Some(format)),
+            None => Err(DatabaseError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+
+    /// Checks that the content of a database file is in the `expected` format
+    pub(crate) fn check(path: &Path, data: &[u8], expected: Self) -> Result<(), DatabaseError> {
+        match Self::detect(data) {
+            Some(format) if format == expected => Ok(()),
+            Some(found) => Err(DatabaseError::FormatMismatch { expected, found }),
+            None => Err(DatabaseError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+}
+
+/// Opens a password-protected database file with the implementation matching its format.
+/// New databases are encrypted with age.
+pub fn open_database(path: PathBuf, secret: String) -> Result<Box<dyn Database>, DatabaseError> {
+    Ok(match VaultFormat::detect_file(&path)? {
+        Some(VaultFormat::Aes) => Box::new(JsonDatabase::new(path, secret)),
+        Some(VaultFormat::Age) | None => Box::new(AgeJsonDatabase::new(path, secret)),
+        #[cfg(feature = "sqlite")]
+        Some(VaultFormat::Sqlite) => Box::new(SqliteDatabase::new(path, secret)),
+        #[cfg(not(feature = "sqlite"))]
+        Some(VaultFormat::Sqlite) => {
+            return Err(DatabaseError::UnsupportedFormat(VaultFormat::Sqlite))
+        }
+        Some(found) => {
+            return Err(DatabaseError::FormatMismatch {
+                expected: VaultFormat::Age,
+                found,
+            })
+        }
+    })
+}
+
+/// Path of a file kept next to a database, named after the whole file name of the database
+/// followed by `.suffix`, e.g. `totp.safe.lock`
+pub fn sidecar_path(vault: &Path, suffix: &str) -> PathBuf {
+    let mut name = vault.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    vault.with_file_name(name)
+}
+
+impl<T: Database + ?Sized> Database for Box<T> {
+    fn get_applications(&self) -> Result<HashMap<String, GenApp>, DatabaseError> {
+        (**self).get_applications()
+    }
+
+    fn save_applications(
+        &self,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        (**self).save_applications(applications)
+    }
+
+    fn set_secret(&mut self, secret: String) -> Result<(), DatabaseError> {
+        (**self).set_secret(secret)
+    }
+
+    fn supports_row_updates(&self) -> bool {
+        (**self).supports_row_updates()
+    }
+
+    fn insert_application(
+        &self,
+        app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        (**self).insert_application(app, applications)
+    }
+
+    fn update_application(
+        &self,
+        name: &str,
+        app: &GenApp,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        (**self).update_application(name, app, applications)
+    }
+
+    fn remove_application(
+        &self,
+        name: &str,
+        applications: &HashMap<String, GenApp>,
+    ) -> Result<(), DatabaseError> {
+        (**self).remove_application(name, applications)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn age_recipients_file() -> Vec<u8> {
+        let identity = age::x25519::Identity::generate();
+        let encryptor =
+            age::Encryptor::with_recipients(vec![Box::new(identity.to_public())]).unwrap();
+        let mut encrypted = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(b"{}").unwrap();
+        writer.finish().unwrap();
+        encrypted
+    }
+
+    #[test]
+    fn detects_formats() {
+        let age_passphrase =
+            b"age-encryption.org/v1\n-> scrypt c2FsdA 18\nYm9keQ\n--- bWFj\nciphertext";
+        assert_eq!(VaultFormat::detect(age_passphrase), Some(VaultFormat::Age));
+        assert_eq!(
+            VaultFormat::detect(&age_recipients_file()),
+            Some(VaultFormat::AgeRecipients)
+        );
+        let mut aes = AES_MAGIC.to_vec();
+        aes.extend([1; 48]);
+        assert_eq!(VaultFormat::detect(&aes), Some(VaultFormat::Aes));
+        assert_eq!(
+            VaultFormat::detect(&[7; IV_SIZE + 48]),
+            Some(VaultFormat::Aes)
+        );
+        let mut sqlite = SQLITE_MAGIC.to_vec();
+        sqlite.extend([0; 84]);
+        assert_eq!(VaultFormat::detect(&sqlite), Some(VaultFormat::Sqlite));
+    }
+
+    #[test]
+    fn rejects_unknown_content() {
+        assert_eq!(VaultFormat::detect(b""), None);
+        assert_eq!(VaultFormat::detect(b"{\"applications\": {}}"), None);
+        assert_eq!(VaultFormat::detect(&[7; IV_SIZE + 32]), None);
+        assert_eq!(VaultFormat::detect(&[7; IV_SIZE + 49]), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        assert!(matches!(VaultFormat::detect_file(&path), Ok(None)));
+        std::fs::write(&path, "not a database").unwrap();
+        let err = VaultFormat::detect_file(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} is not a RusTOTPony database or its format is unknown",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn tells_which_format_a_file_is_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.safe");
+        std::fs::write(&path, age_recipients_file()).unwrap();
+        let err = open_database(path, String::from("password")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "The database is encrypted with age public keys and can't be opened with an age passphrase"
+        );
+    }
+}